@@ -1,3 +1,4 @@
+use crate::paste::expiration::Expiry;
 use crate::CURRENT_DIRECTORY;
 use config::{Environment, Value};
 use serde::Deserialize;
@@ -40,6 +41,8 @@ pub struct Settings {
 pub struct Application {
     pub host: String,
     pub port: u16,
+    /// Address copycat is reachable at, used for links in API responses
+    pub public_url: String,
     #[serde(rename = "body_limit_in_bytes")]
     pub body_limit: usize,
 }
@@ -61,18 +64,70 @@ pub struct Storage {
     pub directory: PathBuf,
     pub id_length: u16,
     pub expiration_in_hours: f32,
+    /// Bounds for the expiration chosen by the uploader
+    pub min_expiration: Option<Expiry>,
+    pub max_expiration: Option<Expiry>,
+    #[serde(default)]
+    pub compression: Compression,
+    /// Identical pastes share their stored content
+    #[serde(default = "default_deduplicate")]
+    pub deduplicate: bool,
+    /// How often expired and orphaned pastes are removed, 0 disables it
+    pub sweep_interval_in_minutes: u64,
+    /// Pastes without metadata are removed instead of getting their metadata rebuilt,
+    /// e.g. after Redis lost its data every paste is removed
+    #[serde(default)]
+    pub remove_orphans: bool,
+    pub s3: Option<S3Storage>,
+}
+
+fn default_deduplicate() -> bool {
+    true
 }
 
 #[derive(Deserialize, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum StorageMethod {
     File,
+    S3,
+    Redis,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+    Gzip,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct S3Storage {
+    pub bucket: String,
+    #[serde(default)]
+    pub prefix: String,
+    pub endpoint: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// MinIO and most self-hosted S3 implementations need path-style requests
+    #[serde(default = "default_path_style")]
+    pub path_style: bool,
+}
+
+fn default_path_style() -> bool {
+    true
 }
 
 #[derive(Deserialize, Clone)]
 pub struct Analyzer {
     pub custom_highlighting_delimiters: Vec<String>,
     pub lines_limits: AnalyzerLinesLimits,
+    #[serde(default)]
+    pub workers: AnalyzerWorkers,
+    #[serde(default)]
+    pub scripts: AnalyzerScripts,
 }
 
 #[derive(Deserialize, Clone)]
@@ -91,6 +146,58 @@ impl AnalyzerLinesLimits {
     }
 }
 
+/// Analyzing and rendering pastes runs on a bounded pool of blocking threads.
+#[derive(Deserialize, Clone)]
+pub struct AnalyzerWorkers {
+    /// Analyses running at the same time
+    pub threads: usize,
+    /// Analyses waiting for a free thread, requests beyond that get a 503
+    pub queue_size: usize,
+    pub timeout_in_seconds: u64,
+}
+
+impl Default for AnalyzerWorkers {
+    fn default() -> Self {
+        Self {
+            threads: std::thread::available_parallelism().map_or(4, |threads| threads.get()),
+            queue_size: 64,
+            timeout_in_seconds: 10,
+        }
+    }
+}
+
+/// Limits for a single run of a detection script, a script that exceeds them is skipped.
+/// 0 disables a limit.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct AnalyzerScripts {
+    /// Changes made to the scripts through the admin API, as JSON Lines
+    pub audit_log: PathBuf,
+    /// Rhai operations (expressions, statements, function calls...)
+    pub max_operations: u64,
+    /// Depth of nested function calls
+    pub max_call_levels: usize,
+    /// Length of strings the script creates
+    pub max_string_size: usize,
+    pub max_array_size: usize,
+    pub max_map_size: usize,
+    pub timeout_in_milliseconds: u64,
+}
+
+impl Default for AnalyzerScripts {
+    fn default() -> Self {
+        Self {
+            audit_log: PathBuf::from("scripts-audit.log"),
+            max_operations: 1_000_000,
+            max_call_levels: 32,
+            max_string_size: 10_000_000,
+            max_array_size: 100_000,
+            max_map_size: 10_000,
+            timeout_in_milliseconds: 1_000,
+        }
+    }
+}
+
 #[derive(Deserialize, Clone)]
 pub struct Ports {
     pub plugins: Value,
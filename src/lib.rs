@@ -3,14 +3,21 @@ pub mod middleware;
 pub mod paste;
 pub mod redis;
 pub mod routes;
+pub mod scripts;
+pub mod storage;
+pub mod sweeper;
+pub mod worker;
 
 use crate::configuration::Settings;
+use crate::scripts::ScriptRegistry;
+use crate::storage::PasteStore;
+use crate::worker::WorkerPool;
 use axum::extract::FromRef;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Redirect, Response};
 use fred::clients::RedisPool;
 use std::path::PathBuf;
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 use tracing::log::error;
 
 static CURRENT_DIRECTORY: LazyLock<PathBuf> = LazyLock::new(|| match std::env::current_dir() {
@@ -22,6 +29,9 @@ static CURRENT_DIRECTORY: LazyLock<PathBuf> = LazyLock::new(|| match std::env::c
 pub struct AppState {
     pub configuration: Settings,
     pub redis_state: RedisState,
+    pub store: Arc<dyn PasteStore>,
+    pub workers: WorkerPool,
+    pub scripts: ScriptRegistry,
 }
 
 #[derive(Clone)]
@@ -1,38 +1,78 @@
 use axum::extract::DefaultBodyLimit;
 use axum::http::{HeaderValue, Method};
-use axum::routing::{get, post};
+use axum::routing::{delete, get, post};
 use axum::{middleware, Router};
-use copycat::configuration::{get_configuration, Settings};
+use copycat::configuration::{get_configuration, Settings, StorageMethod};
 use copycat::middleware::{api_admin_middleware, api_middleware};
-use copycat::paste::analyzer::PasteAnalyzer;
 use copycat::redis::get_redis_connection;
 use copycat::routes::api::{
-    all::get_api_all, frontend::detail::get_frontend_api_detail, frontend::paste::get_paste,
-    leaks::get_api_leaks, plugins::get_api_plugins, ports::get_api_ports,
+    admin::scripts::{
+        delete_script, get_platform_scripts, get_script, get_script_metrics, get_scripts,
+        post_disable_script, post_enable_script, post_validate_script, put_script,
+    },
+    all::get_api_all,
+    detail::get_api_detail,
+    detections::get_api_detections,
+    frontend::paste::get_paste,
+    leaks::get_api_leaks,
+    meta::get_api_meta,
+    paste::delete_api_paste,
+    plugins::get_api_plugins,
+    ports::get_api_ports,
+    upload::post_api_upload,
 };
 use copycat::routes::{
-    get::raw::get_raw,
-    post::{get_expiration, upload::post_upload},
+    get::raw::{get_raw, get_raw_member},
+    post::{raw::upload_raw, upload::post_upload},
 };
+use copycat::scripts::fixtures::{self, FIXTURES_DIRECTORY};
+use copycat::scripts::{ScriptRegistry, Scripts};
+use copycat::storage::dedup::{DedupStore, BLOBS_NAMESPACE};
+use copycat::storage::get_store;
+use copycat::sweeper::Sweeper;
+use copycat::worker::WorkerPool;
 use copycat::{AppState, RedisState};
-use fred::{clients::RedisPool, interfaces::KeysInterface};
 use mclog::analyzer::dynamic::{ScriptPlatform, SCRIPTS_DIRECTORY};
 use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use tower_http::{cors::CorsLayer, limit::RequestBodyLimitLayer};
-use tracing::debug;
+use tracing::{debug, error};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() {
     let configuration = get_configuration().expect("Failed to read configuration");
+
+    if std::env::args().nth(1).as_deref() == Some("test-scripts") {
+        test_scripts(&configuration).await;
+    }
+
     let redis_pool = get_redis_connection(&configuration)
         .await
         .expect("Failed to connect to Redis server");
 
-    Initializer::new(redis_pool.clone(), configuration.clone())
-        .init()
+    let store = get_store(&configuration, &redis_pool).expect("Failed to initialize paste storage");
+
+    let workers = WorkerPool::new(&configuration.analyzer.workers);
+
+    let mut sweeper = Sweeper::new(
+        redis_pool.clone(),
+        store.clone(),
+        configuration.clone(),
+        workers.clone(),
+    );
+
+    Initializer::new(store.clone(), configuration.clone())
+        .init(&mut sweeper)
         .await;
 
+    let sweep_interval_in_minutes = configuration.storage.sweep_interval_in_minutes;
+    if sweep_interval_in_minutes > 0 {
+        sweeper.spawn(Duration::from_secs(sweep_interval_in_minutes * 60));
+    }
+
     let app = Application::new(&configuration);
 
     let cors_layer = CorsLayer::new()
@@ -43,18 +83,47 @@ async fn main() {
                 .parse::<HeaderValue>()
                 .expect("Couldn't parse allow_origin from config into HeaderValue"),
         )
-        .allow_methods([Method::GET, Method::POST]);
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE]);
+
+    let scripts = ScriptRegistry::load(&configuration.analyzer.scripts);
+    // dropping the watcher stops watching, it has to live as long as the server
+    let _scripts_watcher = scripts
+        .watch()
+        .inspect_err(|err| error!("Couldn't watch scripts, changes won't be reloaded: {err}"))
+        .ok();
 
     let redis_state = RedisState { pool: redis_pool };
 
     let app_state = AppState {
         configuration: configuration.clone(),
         redis_state,
+        store,
+        workers,
+        scripts,
     };
 
     let frontend_api_router = Router::new()
         .route("/paste/:id", get(get_paste))
-        .route("/detail/:detail_id", get(get_frontend_api_detail))
+        .route("/detail/:detail_id", get(get_api_detail))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            api_admin_middleware,
+        ));
+
+    let admin_api_router = Router::new()
+        .route("/scripts/metrics", get(get_script_metrics))
+        .route("/scripts/validate", post(post_validate_script))
+        .route("/scripts", get(get_scripts))
+        .route("/scripts/:platform", get(get_platform_scripts))
+        .route(
+            "/scripts/:platform/:name",
+            get(get_script).put(put_script).delete(delete_script),
+        )
+        .route("/scripts/:platform/:name/enable", post(post_enable_script))
+        .route(
+            "/scripts/:platform/:name/disable",
+            post(post_disable_script),
+        )
         .layer(middleware::from_fn_with_state(
             app_state.clone(),
             api_admin_middleware,
@@ -62,19 +131,27 @@ async fn main() {
 
     let api_router = Router::new()
         .route("/all/:id", get(get_api_all))
+        .route("/detections/:id", get(get_api_detections))
+        .route("/detail/:detail_id", get(get_api_detail))
         .route("/plugins/:id", get(get_api_plugins))
         .route("/ports/:id", get(get_api_ports))
         .route("/leaks/:id", get(get_api_leaks))
-        //.nest("/admin", admin_api_router)
+        .route("/meta/:id", get(get_api_meta))
+        .route("/upload", post(post_api_upload))
+        .route("/upload/raw", post(upload_raw))
+        .nest("/admin", admin_api_router)
         .nest("/frontend", frontend_api_router)
         .layer(middleware::from_fn_with_state(
             app_state.clone(),
             api_middleware,
-        ));
+        ))
+        // the deletion token is the authorization, so this is reachable even when the API isn't public
+        .route("/paste/:id", delete(delete_api_paste));
 
     let router = Router::new()
         .route("/raw/:id", get(get_raw))
-        .route("/upload", post(post_upload))
+        .route("/raw/:id/*file", get(get_raw_member))
+        .route("/upload", post(post_upload).put(upload_raw))
         .nest("/api", api_router);
 
     let router = router
@@ -121,6 +198,44 @@ async fn shutdown_signal() {
     }
 }
 
+/// `copycat test-scripts [directory]` checks the scripts against fixture logs
+/// and exits non-zero if any of them fails.
+async fn test_scripts(configuration: &Settings) -> ! {
+    init_tracing();
+
+    let directory = std::env::args()
+        .nth(2)
+        .unwrap_or_else(|| FIXTURES_DIRECTORY.to_string());
+
+    let scripts = Scripts::load(&configuration.analyzer.scripts, None);
+
+    let passed = fixtures::test_scripts(
+        &scripts,
+        &configuration.analyzer.lines_limits,
+        Path::new(&directory),
+    )
+    .await;
+
+    match passed {
+        Ok(true) => std::process::exit(0),
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("Couldn't test scripts: {err:#}");
+            std::process::exit(2)
+        }
+    }
+}
+
+fn init_tracing() {
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "copycat=debug,tower_http=debug,axum::rejection=trace".into()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+}
+
 struct Application {
     host: String,
     port: u16,
@@ -146,33 +261,23 @@ impl Application {
 }
 
 struct Initializer {
-    pool: RedisPool,
+    store: Arc<DedupStore>,
     configuration: Settings,
 }
 
 impl Initializer {
-    fn new(redis_pool: RedisPool, configuration: Settings) -> Self {
+    fn new(store: Arc<DedupStore>, configuration: Settings) -> Self {
         Self {
-            pool: redis_pool,
+            store,
             configuration,
         }
     }
 
-    async fn init(&mut self) {
-        self.init_tracing();
+    async fn init(&mut self, sweeper: &mut Sweeper) {
+        init_tracing();
         self.init_directories();
-        self.generate_content_types().await;
-    }
-
-    fn init_tracing(&self) {
-        tracing_subscriber::registry()
-            .with(
-                tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                    "copycat=debug,tower_http=debug,axum::rejection=trace".into()
-                }),
-            )
-            .with(tracing_subscriber::fmt::layer())
-            .init();
+        self.compress_stored_pastes().await;
+        self.sweep_pastes(sweeper).await;
     }
 
     fn init_directories(&self) {
@@ -181,9 +286,14 @@ impl Initializer {
     }
 
     fn init_data_dir(&self) {
-        let data_directory = &self.configuration.storage.directory;
-        if !data_directory.exists() {
-            std::fs::create_dir_all(data_directory).expect("Data directory couldn't be created");
+        if !matches!(self.configuration.storage.method, StorageMethod::File) {
+            return;
+        }
+
+        // deduplicated content is kept in a directory of its own
+        let blobs_directory = self.configuration.storage.directory.join(BLOBS_NAMESPACE);
+        if !blobs_directory.exists() {
+            std::fs::create_dir_all(blobs_directory).expect("Data directory couldn't be created");
         }
     }
 
@@ -203,42 +313,17 @@ impl Initializer {
         }
     }
 
-    async fn generate_content_types(&mut self) {
-        for file in self
-            .configuration
-            .storage
-            .directory
-            .read_dir()
-            .expect("Couldn't read storage directory")
-        {
-            let file = file.unwrap();
-            let file_name = file
-                .file_name()
-                .to_str()
-                .expect("Failed reading file name to str")
-                .to_string();
-
-            let paste_type: Option<String> = self.pool.get(file_name.clone()).await.unwrap_or(None);
-
-            if paste_type.is_none() {
-                let content = tokio::fs::read_to_string(file.path()).await.unwrap();
-                let content_as_bytes = content.as_bytes();
-
-                let paste_analyzer = PasteAnalyzer::new();
-                let paste_type = paste_analyzer.paste_type(content_as_bytes);
-
-                let _: () = self
-                    .pool
-                    .set(
-                        file_name.clone(),
-                        paste_type,
-                        get_expiration(&self.configuration),
-                        None,
-                        false,
-                    )
-                    .await
-                    .unwrap();
-            }
-        }
+    async fn compress_stored_pastes(&self) {
+        self.store
+            .compress_uncompressed()
+            .await
+            .expect("Couldn't compress stored pastes");
+    }
+
+    async fn sweep_pastes(&self, sweeper: &mut Sweeper) {
+        sweeper
+            .sweep_at_startup()
+            .await
+            .expect("Couldn't sweep stored pastes");
     }
 }
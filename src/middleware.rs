@@ -3,7 +3,7 @@ use crate::AppState;
 use axum::body::Body;
 use axum::{
     extract::State,
-    http::{Request, StatusCode},
+    http::{HeaderMap, Request, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
     Json,
@@ -57,6 +57,18 @@ async fn reject_unauthorized_request(
     }
 }
 
+/// Whether the request carries the admin `X-API-KEY`.
+pub fn has_valid_api_key(headers: &HeaderMap) -> bool {
+    let Ok(required_api_key) = std::env::var("APP_API_KEY") else {
+        return false;
+    };
+
+    headers
+        .get("x-api-key")
+        .and_then(|key| key.to_str().ok())
+        .is_some_and(|key| key == required_api_key)
+}
+
 fn reject(message: &str) -> Result<Response, ApiError> {
     Ok((
         StatusCode::UNAUTHORIZED,
@@ -1,5 +1,5 @@
 use fred::types::RedisValue;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tracing::log::warn;
 
 pub struct PasteAnalyzer;
@@ -44,7 +44,7 @@ impl PasteAnalyzer {
     }
 }
 
-#[derive(Serialize, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum PasteType {
     Log,
@@ -0,0 +1,151 @@
+use flate2::read::MultiGzDecoder;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+
+/// Bytes [`detect`] needs to tell every kind of archive apart
+pub const HEADER_LENGTH: usize = ZIP_MAGIC.len();
+
+/// Zips of a whole `logs` folder can contain hundreds of old logs, only the newest ones are kept.
+const MAX_ZIP_ENTRIES: usize = 10;
+
+#[derive(Clone, Copy, Debug)]
+pub enum ArchiveKind {
+    Gzip,
+    Zip,
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// The extracted files together are larger than `paste.size_limit`
+    TooLarge,
+    NoLogFiles,
+    Invalid(io::Error),
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(err: io::Error) -> Self {
+        Self::Invalid(err)
+    }
+}
+
+impl From<zip::result::ZipError> for ArchiveError {
+    fn from(err: zip::result::ZipError) -> Self {
+        Self::Invalid(err.into())
+    }
+}
+
+pub struct ArchiveEntry {
+    pub name: String,
+    pub content: Vec<u8>,
+}
+
+pub fn detect(content: &[u8]) -> Option<ArchiveKind> {
+    if content.starts_with(&GZIP_MAGIC) {
+        Some(ArchiveKind::Gzip)
+    } else if content.starts_with(&ZIP_MAGIC) {
+        Some(ArchiveKind::Zip)
+    } else {
+        None
+    }
+}
+
+/// Extracts the log files from an uploaded archive. All extracted files together are capped at
+/// `limit` bytes, so a small archive can't expand into something huge.
+///
+/// `name` is the file name of the archive itself, it's used to name the content of gzip files.
+pub fn extract(
+    kind: ArchiveKind,
+    archive: &[u8],
+    name: Option<&str>,
+    limit: u64,
+) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+    match kind {
+        ArchiveKind::Gzip => {
+            let name = name
+                .and_then(|name| name.strip_suffix(".gz"))
+                .unwrap_or("latest.log");
+
+            Ok(vec![ArchiveEntry {
+                name: name.to_string(),
+                content: gunzip(archive, limit)?,
+            }])
+        }
+        ArchiveKind::Zip => unzip(archive, limit),
+    }
+}
+
+fn unzip(archive: &[u8], limit: u64) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+    let mut archive = ZipArchive::new(Cursor::new(archive))?;
+
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| is_log_file(name))
+        .map(str::to_string)
+        .collect();
+
+    // latest.log is what everyone is looking for, the rest is only useful without it
+    if let Some(latest) = names.iter().find(|name| file_name(name) == "latest.log") {
+        names = vec![latest.clone()];
+    }
+
+    if names.is_empty() {
+        return Err(ArchiveError::NoLogFiles);
+    }
+
+    // old logs are named by date, so the newest are last
+    names.sort();
+    let skip = names.len().saturating_sub(MAX_ZIP_ENTRIES);
+
+    let mut entries = Vec::new();
+    let mut remaining = limit;
+
+    for name in names.into_iter().skip(skip) {
+        let file = archive.by_name(&name)?;
+        let mut content = read_limited(file, remaining)?;
+
+        if let Some(ArchiveKind::Gzip) = detect(&content) {
+            content = gunzip(&content, remaining)?;
+        }
+        remaining -= content.len() as u64;
+
+        entries.push(ArchiveEntry {
+            name: file_name(&name).trim_end_matches(".gz").to_string(),
+            content,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn gunzip(archive: &[u8], limit: u64) -> Result<Vec<u8>, ArchiveError> {
+    read_limited(MultiGzDecoder::new(archive), limit)
+}
+
+fn read_limited(reader: impl Read, limit: u64) -> Result<Vec<u8>, ArchiveError> {
+    let mut content = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut content)?;
+
+    if content.len() as u64 > limit {
+        return Err(ArchiveError::TooLarge);
+    }
+
+    Ok(content)
+}
+
+fn is_log_file(name: &str) -> bool {
+    !name.ends_with('/')
+        && [".log", ".log.gz", ".txt"]
+            .iter()
+            .any(|extension| name.ends_with(extension))
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
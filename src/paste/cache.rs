@@ -0,0 +1,156 @@
+use crate::configuration::Settings;
+use crate::paste::metadata::hash;
+use async_compression::tokio::bufread::{ZstdDecoder, ZstdEncoder};
+use axum::body::Bytes;
+use fred::clients::RedisPool;
+use fred::error::RedisError;
+use fred::interfaces::KeysInterface;
+use fred::types::{Expiration, RedisValue};
+use mclog::analyzer::dynamic::Detection;
+use serde::{Deserialize, Serialize};
+use std::io;
+use tokio::io::AsyncReadExt;
+
+/// Analyzer results of a log paste, cached so the log isn't read and the scripts aren't run
+/// on every request. Stored as JSON under `<id>:analysis` until the paste expires.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Analysis {
+    /// [`Scripts::version`](crate::scripts::Scripts::version) the detections were made with
+    scripts_version: String,
+    /// `DynamicAnalyzerDetails` as JSON
+    pub details: serde_json::Value,
+    pub detections: Vec<Detection>,
+    /// Detections marked `private`, only shown to API clients with the API key
+    pub private_detections: Vec<Detection>,
+}
+
+impl Analysis {
+    /// Splits the detections into public and private ones.
+    pub fn new(
+        scripts_version: String,
+        details: serde_json::Value,
+        detections: Vec<Detection>,
+    ) -> Self {
+        let (private_detections, detections) = detections
+            .into_iter()
+            .partition(|detection| detection.private.is_some_and(|is_private| is_private));
+
+        Self {
+            scripts_version,
+            details,
+            detections,
+            private_detections,
+        }
+    }
+
+    /// Public and private detections, sorted by level
+    pub fn all_detections(&self) -> Vec<Detection> {
+        let mut detections = self.detections.clone();
+        detections.extend(self.private_detections.iter().cloned());
+        detections.sort_by_key(|item| item.level);
+
+        detections
+    }
+
+    fn key(id: &str) -> String {
+        format!("{id}:analysis")
+    }
+
+    /// Returns `None` when nothing is cached or the scripts changed since.
+    pub async fn load(
+        pool: &RedisPool,
+        id: &str,
+        scripts_version: &str,
+    ) -> Result<Option<Self>, RedisError> {
+        let value: Option<String> = pool.get(Self::key(id)).await?;
+
+        let analysis = value
+            .and_then(|value| serde_json::from_str::<Self>(&value).ok())
+            .filter(|analysis| analysis.scripts_version == scripts_version);
+
+        Ok(analysis)
+    }
+
+    /// `expires_at` should be the expiration of the paste, so the cache doesn't outlive it.
+    pub async fn save(
+        &self,
+        pool: &RedisPool,
+        id: &str,
+        expires_at: Option<u64>,
+    ) -> Result<(), RedisError> {
+        let value = serde_json::to_string(self).expect("Analysis is always serializable");
+        let expiration = expires_at.map(|expires_at| Expiration::EXAT(expires_at as i64));
+
+        pool.set(Self::key(id), value, expiration, None, false)
+            .await
+    }
+}
+
+/// Rendered HTML of a log paste, zstd compressed. It's stored under `<id>:html` after the
+/// [`tag`](RenderedHtml::tag) of the render, so the log is rendered again when the highlighting
+/// delimiters change.
+pub struct RenderedHtml;
+
+impl RenderedHtml {
+    fn key(id: &str) -> String {
+        format!("{id}:html")
+    }
+
+    /// Identifies what the HTML was rendered from, it's always 64 bytes long.
+    pub fn tag(content_hash: &str, configuration: &Settings) -> String {
+        let delimiters = &configuration.analyzer.custom_highlighting_delimiters;
+
+        hash(format!("{content_hash}\n{}", delimiters.join("\n")).as_bytes())
+    }
+
+    pub async fn load(pool: &RedisPool, id: &str, tag: &str) -> io::Result<Option<String>> {
+        let value: Option<Vec<u8>> = pool.get(Self::key(id)).await.map_err(io::Error::other)?;
+
+        let Some(compressed) = value
+            .as_deref()
+            .and_then(|value| value.strip_prefix(tag.as_bytes()))
+        else {
+            return Ok(None);
+        };
+
+        let mut html = String::new();
+        ZstdDecoder::new(compressed)
+            .read_to_string(&mut html)
+            .await?;
+
+        Ok(Some(html))
+    }
+
+    /// `expires_at` should be the expiration of the paste, so the cache doesn't outlive it.
+    pub async fn save(
+        pool: &RedisPool,
+        id: &str,
+        tag: &str,
+        html: &str,
+        expires_at: Option<u64>,
+    ) -> io::Result<()> {
+        let mut value = tag.as_bytes().to_vec();
+        ZstdEncoder::new(html.as_bytes())
+            .read_to_end(&mut value)
+            .await?;
+
+        let expiration = expires_at.map(|expires_at| Expiration::EXAT(expires_at as i64));
+
+        pool.set(
+            Self::key(id),
+            RedisValue::Bytes(Bytes::from(value)),
+            expiration,
+            None,
+            false,
+        )
+        .await
+        .map_err(io::Error::other)
+    }
+}
+
+/// Removes everything cached for the paste.
+pub async fn delete(pool: &RedisPool, id: &str) -> Result<(), RedisError> {
+    pool.del(vec![Analysis::key(id), RenderedHtml::key(id)])
+        .await
+}
@@ -0,0 +1,189 @@
+use crate::configuration::Settings;
+use crate::routes::post::expiration_in_seconds;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Paste lifetime requested by the uploader, e.g. `30m`, `1h`, `1d`, `7d` or `never`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Expiry {
+    Never,
+    /// Seconds after the upload
+    After(u64),
+}
+
+impl FromStr for Expiry {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+
+        if value.eq_ignore_ascii_case("never") {
+            return Ok(Expiry::Never);
+        }
+
+        let invalid = || format!("Invalid expiration '{value}'");
+
+        let (unit_index, _) = value.char_indices().last().ok_or_else(invalid)?;
+        let (amount, unit) = value.split_at(unit_index);
+        let amount: u64 = amount.parse().map_err(|_| invalid())?;
+
+        let unit_in_seconds = match unit {
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            "w" => 604800,
+            _ => return Err(invalid()),
+        };
+
+        match amount.checked_mul(unit_in_seconds) {
+            Some(0) | None => Err(invalid()),
+            Some(seconds) => Ok(Expiry::After(seconds)),
+        }
+    }
+}
+
+impl TryFrom<String> for Expiry {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Expiry {
+    fn in_seconds(self) -> Option<u64> {
+        match self {
+            Expiry::Never => None,
+            Expiry::After(seconds) => Some(seconds),
+        }
+    }
+}
+
+/// Returns how many seconds the paste should live for (`None` = forever).
+///
+/// Without a requested expiry, `storage.expiration_in_hours` is used. A requested expiry
+/// is clamped to `storage.min_expiration` and `storage.max_expiration`.
+pub fn choose_expiration(
+    requested: Option<&str>,
+    configuration: &Settings,
+) -> Result<Option<u64>, String> {
+    let Some(requested) = requested else {
+        return Ok(expiration_in_seconds(configuration));
+    };
+
+    let mut seconds = requested.parse::<Expiry>()?.in_seconds();
+
+    if let Some(Expiry::After(max)) = configuration.storage.max_expiration {
+        seconds = Some(seconds.map_or(max, |seconds| seconds.min(max)));
+    }
+
+    if let Some(Expiry::After(min)) = configuration.storage.min_expiration {
+        seconds = seconds.map(|seconds| seconds.max(min));
+    }
+
+    Ok(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn configuration(min: Option<&str>, max: Option<&str>) -> Settings {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("configuration");
+
+        let mut configuration: Settings = config::Config::builder()
+            .add_source(config::File::from(directory.join("config.toml")))
+            .add_source(config::File::from(directory.join("ports.toml")))
+            .build()
+            .and_then(config::Config::try_deserialize)
+            .expect("shipped configuration is valid");
+
+        configuration.storage.expiration_in_hours = 24.0;
+        configuration.storage.min_expiration = min.map(|min| min.parse().unwrap());
+        configuration.storage.max_expiration = max.map(|max| max.parse().unwrap());
+
+        configuration
+    }
+
+    #[test]
+    fn parses_every_unit() {
+        assert_eq!("30m".parse(), Ok(Expiry::After(30 * 60)));
+        assert_eq!("2h".parse(), Ok(Expiry::After(2 * 3600)));
+        assert_eq!("1d".parse(), Ok(Expiry::After(86400)));
+        assert_eq!("2w".parse(), Ok(Expiry::After(2 * 604800)));
+        assert_eq!(" 7d ".parse(), Ok(Expiry::After(7 * 86400)));
+        assert_eq!("never".parse(), Ok(Expiry::Never));
+        assert_eq!("Never".parse(), Ok(Expiry::Never));
+    }
+
+    #[test]
+    fn rejects_invalid_expirations() {
+        for value in [
+            "",
+            "d",
+            "0d",
+            "1",
+            "1y",
+            "-1d",
+            "1.5h",
+            "h1",
+            "99999999999999999w",
+        ] {
+            assert!(value.parse::<Expiry>().is_err(), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn uses_the_configured_expiration_when_none_is_requested() {
+        let configuration = configuration(Some("1h"), Some("7d"));
+
+        assert_eq!(choose_expiration(None, &configuration), Ok(Some(86400)));
+    }
+
+    #[test]
+    fn clamps_the_requested_expiration() {
+        let configuration = configuration(Some("1h"), Some("7d"));
+
+        assert_eq!(
+            choose_expiration(Some("30m"), &configuration),
+            Ok(Some(3600))
+        );
+        assert_eq!(
+            choose_expiration(Some("2d"), &configuration),
+            Ok(Some(2 * 86400))
+        );
+        assert_eq!(
+            choose_expiration(Some("2w"), &configuration),
+            Ok(Some(7 * 86400))
+        );
+        assert_eq!(
+            choose_expiration(Some("never"), &configuration),
+            Ok(Some(7 * 86400))
+        );
+        assert!(choose_expiration(Some("1y"), &configuration).is_err());
+    }
+
+    #[test]
+    fn never_expires_without_a_maximum() {
+        let configuration = configuration(Some("1h"), None);
+
+        assert_eq!(choose_expiration(Some("never"), &configuration), Ok(None));
+        assert_eq!(
+            choose_expiration(Some("1w"), &configuration),
+            Ok(Some(604800))
+        );
+    }
+
+    #[test]
+    fn max_expiration_never_leaves_the_requested_expiration() {
+        let configuration = configuration(None, Some("never"));
+
+        assert_eq!(choose_expiration(Some("never"), &configuration), Ok(None));
+        assert_eq!(
+            choose_expiration(Some("1d"), &configuration),
+            Ok(Some(86400))
+        );
+    }
+}
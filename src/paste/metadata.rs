@@ -0,0 +1,238 @@
+use crate::configuration::Settings;
+use crate::paste::analyzer::{PasteAnalyzer, PasteType};
+use crate::paste::Paste;
+use crate::worker::{WorkerError, WorkerPool};
+use fred::clients::RedisPool;
+use fred::error::RedisError;
+use fred::interfaces::{KeysInterface, SortedSetsInterface};
+use fred::types::Expiration;
+use mclog::analyzer::Analyzer;
+use mclog::log::Log;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Sorted set of the ids of expiring pastes, scored by when they expire. The metadata is gone
+/// as soon as a paste expires, this tells the [`Sweeper`](crate::sweeper::Sweeper) that its
+/// content expired instead of losing its metadata.
+const EXPIRATIONS_KEY: &str = "expirations";
+
+/// Everything copycat knows about a paste, stored as JSON under the paste id.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PasteMetadata {
+    pub paste_type: PasteType,
+    pub size: u64,
+    pub line_count: u64,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    /// Unix timestamp in seconds, `None` if the paste never expires
+    pub expires_at: Option<u64>,
+    pub filename: Option<String>,
+    /// Name of the gzip or zip file the paste was extracted from
+    #[serde(default)]
+    pub archive: Option<String>,
+    /// SHA-256 of the API key the paste was uploaded with
+    pub uploader: Option<String>,
+    pub platform: Option<String>,
+    pub version: Option<String>,
+    /// SHA-256 of the paste content
+    pub content_hash: String,
+    /// The paste is deleted after it's viewed for the first time
+    #[serde(default)]
+    pub burn_after_read: bool,
+    /// SHA-256 of the token that allows deleting the paste
+    #[serde(default)]
+    pub deletion_token: Option<String>,
+    /// Files uploaded together with this paste, including the paste itself
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<BundleMember>,
+}
+
+/// A single file of a bundle. Every member is a paste of its own, the bundle id
+/// belongs to the member that's analyzed (the log, if there is one).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleMember {
+    pub name: String,
+    pub id: String,
+    pub paste_type: PasteType,
+}
+
+impl PasteMetadata {
+    /// `expires_in` is in seconds, `None` means the paste never expires.
+    pub async fn generate(
+        paste: &Paste,
+        content: Vec<u8>,
+        expires_in: Option<u64>,
+        configuration: &Settings,
+        workers: &WorkerPool,
+    ) -> Result<Self, WorkerError> {
+        let created_at = unix_timestamp();
+
+        let (paste_type, line_count, content_hash, size) = workers
+            .run(move || {
+                (
+                    PasteAnalyzer::new().paste_type(&content),
+                    line_count(&content),
+                    hash(&content),
+                    content.len() as u64,
+                )
+            })
+            .await?;
+
+        let mut metadata = Self {
+            paste_type,
+            size,
+            line_count,
+            created_at,
+            expires_at: expires_in.map(|expires_in| created_at + expires_in),
+            filename: None,
+            archive: None,
+            uploader: None,
+            platform: None,
+            version: None,
+            content_hash,
+            burn_after_read: false,
+            deletion_token: None,
+            members: Vec::new(),
+        };
+
+        if let PasteType::Log = metadata.paste_type {
+            metadata
+                .detect_server(paste, configuration, workers)
+                .await?;
+        }
+
+        Ok(metadata)
+    }
+
+    async fn detect_server(
+        &mut self,
+        paste: &Paste,
+        configuration: &Settings,
+        workers: &WorkerPool,
+    ) -> Result<(), WorkerError> {
+        let lines = match paste.lines().await {
+            Ok(lines) => lines,
+            Err(err) => {
+                warn!("Couldn't read lines of paste '{}': {err}", paste.id());
+                return Ok(());
+            }
+        };
+
+        let log = Log::new(lines);
+        let lines = log
+            .first_n_lines_hideips(configuration.analyzer.lines_limits.server)
+            .await;
+
+        let details = workers
+            .run(move || Analyzer::new(&lines).build(0, 0))
+            .await?;
+
+        self.version = details.version;
+        self.platform = serde_json::to_value(details.platform)
+            .ok()
+            .and_then(|platform| platform.as_str().map(str::to_string));
+
+        Ok(())
+    }
+
+    /// Pastes uploaded before metadata existed only have their type stored.
+    fn legacy(paste_type: PasteType) -> Self {
+        Self {
+            paste_type,
+            size: 0,
+            line_count: 0,
+            created_at: 0,
+            expires_at: None,
+            filename: None,
+            archive: None,
+            uploader: None,
+            platform: None,
+            version: None,
+            content_hash: String::new(),
+            burn_after_read: false,
+            deletion_token: None,
+            members: Vec::new(),
+        }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.content_hash.is_empty()
+    }
+
+    pub async fn load(pool: &RedisPool, id: &str) -> Result<Option<Self>, RedisError> {
+        let value: Option<String> = pool.get(id).await?;
+
+        let metadata = value.map(|value| match serde_json::from_str(&value) {
+            Ok(metadata) => metadata,
+            Err(_) => Self::legacy(PasteType::from(value)),
+        });
+
+        Ok(metadata)
+    }
+
+    /// Saves the metadata until the paste expires, the expiration is recorded first so
+    /// there's never metadata without it.
+    pub async fn save(&self, pool: &RedisPool, id: &str) -> Result<(), RedisError> {
+        let value = serde_json::to_string(self).expect("PasteMetadata is always serializable");
+
+        let expiration = match self.expires_at {
+            Some(expires_at) => {
+                let _: () = pool
+                    .zadd(
+                        EXPIRATIONS_KEY,
+                        None,
+                        None,
+                        false,
+                        false,
+                        (expires_at as f64, id),
+                    )
+                    .await?;
+                Some(Expiration::EXAT(expires_at as i64))
+            }
+            None => {
+                let _: () = pool.zrem(EXPIRATIONS_KEY, id).await?;
+                None
+            }
+        };
+
+        pool.set(id, value, expiration, None, false).await
+    }
+
+    /// When the paste expires, known even after its metadata expired. `None` for pastes that
+    /// never expire and ones whose expiration was forgotten by [`PasteMetadata::forget_expired`].
+    pub async fn expiration(pool: &RedisPool, id: &str) -> Result<Option<u64>, RedisError> {
+        let score: Option<f64> = pool.zscore(EXPIRATIONS_KEY, id).await?;
+
+        Ok(score.map(|score| score as u64))
+    }
+
+    /// Forgets the expiration of pastes that expired at `until` or before
+    pub async fn forget_expired(pool: &RedisPool, until: u64) -> Result<(), RedisError> {
+        pool.zremrangebyscore(EXPIRATIONS_KEY, 0_i64, until as i64)
+            .await
+    }
+}
+
+pub fn hash(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before UNIX epoch")
+        .as_secs()
+}
+
+fn line_count(content: &[u8]) -> u64 {
+    let newlines = content.iter().filter(|byte| **byte == b'\n').count() as u64;
+
+    match content.last() {
+        Some(b'\n') | None => newlines,
+        Some(_) => newlines + 1,
+    }
+}
@@ -1,41 +1,46 @@
-use crate::configuration::Settings;
-use crate::CURRENT_DIRECTORY;
-use std::path::PathBuf;
-use tokio::io::AsyncBufReadExt;
+use crate::storage::{PasteLines, PasteStore};
+use std::io;
+use std::sync::Arc;
 
 pub mod analyzer;
+pub mod archive;
+pub mod cache;
+pub mod expiration;
+pub mod metadata;
 
 pub struct Paste {
     id: String,
-    configuration: Settings,
+    store: Arc<dyn PasteStore>,
 }
 
 impl Paste {
-    pub fn new(id: String, configuration: Settings) -> Self {
-        Self { id, configuration }
+    pub fn new(id: String, store: Arc<dyn PasteStore>) -> Self {
+        Self { id, store }
     }
 
-    pub fn data_directory(&self) -> PathBuf {
-        CURRENT_DIRECTORY.join(&self.configuration.storage.directory)
+    pub fn id(&self) -> &str {
+        &self.id
     }
 
-    pub fn file_path(&self) -> PathBuf {
-        self.data_directory().join(&self.id)
+    pub async fn exists(&self) -> io::Result<bool> {
+        self.store.exists(&self.id).await
     }
 
-    pub async fn file(&self) -> tokio::io::Result<tokio::fs::File> {
-        tokio::fs::File::open(self.file_path()).await
-    }
+    pub async fn content(&self) -> io::Result<String> {
+        let content = self.store.get(&self.id).await?;
 
-    pub async fn lines(
-        &self,
-    ) -> tokio::io::Result<tokio::io::Lines<tokio::io::BufReader<tokio::fs::File>>> {
-        let file = self.file().await;
+        String::from_utf8(content).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
 
-        let reader = tokio::io::BufReader::new(file?);
+    pub async fn lines(&self) -> io::Result<PasteLines> {
+        self.store.lines(&self.id).await
+    }
 
-        let lines = reader.lines();
+    pub async fn expire(&self, expires_at: Option<u64>) -> io::Result<()> {
+        self.store.expire(&self.id, expires_at).await
+    }
 
-        Ok(lines)
+    pub async fn delete(&self) -> io::Result<()> {
+        self.store.delete(&self.id).await
     }
 }
@@ -0,0 +1,275 @@
+use crate::routes::api::{get_analyzer_details, ApiError};
+use crate::scripts::files::{self, AuditAction, ScriptEntry, ScriptFile};
+use crate::scripts::validation::Validation;
+use crate::scripts::Match;
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use mclog::analyzer::dynamic::ScriptPlatform;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+
+/// Runs, errors and exceeded limits of every loaded script
+pub async fn get_script_metrics(State(app_state): State<AppState>) -> Response {
+    Json(app_state.scripts.current().metrics()).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct ValidateRequest {
+    /// Script followed by `///` and its detections in TOML, like the files in `scripts/`
+    source: String,
+    /// Paste the script is run against
+    id: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidateResponse {
+    valid: bool,
+    /// Why the script or its detections don't compile
+    error: Option<String>,
+    #[serde(flatten)]
+    validation: Option<Validation>,
+    dry_run: Option<DryRun>,
+}
+
+#[derive(Serialize)]
+pub struct DryRun {
+    /// Private detections included
+    detections: Vec<Match>,
+    error: Option<String>,
+}
+
+pub async fn post_validate_script(
+    State(app_state): State<AppState>,
+    Json(request): Json<ValidateRequest>,
+) -> Result<Response, ApiError> {
+    let scripts = app_state.scripts.current();
+
+    let script = match scripts.compile(std::path::Path::new("validate.rhai"), &request.source) {
+        Ok(script) => script,
+        Err(err) => {
+            let response = ValidateResponse {
+                valid: false,
+                error: Some(format!("{err:#}")),
+                validation: None,
+                dry_run: None,
+            };
+            return Ok(Json(response).into_response());
+        }
+    };
+
+    let validation = Validation::new(&script);
+
+    let dry_run = match request.id {
+        Some(id) => {
+            let limits = &app_state.configuration.analyzer.lines_limits;
+            let details =
+                get_analyzer_details(id, &app_state, limits.plugins, limits.ports).await?;
+
+            let result = app_state
+                .workers
+                .run(move || scripts.dry_run(&script, details))
+                .await?;
+
+            Some(match result {
+                Ok(detections) => DryRun {
+                    detections,
+                    error: None,
+                },
+                Err(err) => DryRun {
+                    detections: Vec::new(),
+                    error: Some(err.to_string()),
+                },
+            })
+        }
+        None => None,
+    };
+
+    let response = ValidateResponse {
+        valid: validation.is_valid(),
+        error: None,
+        validation: Some(validation),
+        dry_run,
+    };
+
+    Ok(Json(response).into_response())
+}
+
+const SCRIPT_NOT_FOUND: &str = "Script not found";
+
+#[derive(Serialize)]
+pub struct ScriptResponse {
+    #[serde(flatten)]
+    entry: ScriptEntry,
+    source: String,
+}
+
+#[derive(Deserialize)]
+pub struct WriteRequest {
+    /// Script followed by `///` and its detections in TOML
+    source: String,
+}
+
+#[derive(Serialize)]
+pub struct WriteResponse {
+    action: AuditAction,
+}
+
+pub async fn get_scripts() -> Result<Response, ApiError> {
+    let mut scripts = Vec::new();
+
+    for platform in ScriptPlatform::iter() {
+        scripts.extend(list(platform).await?);
+    }
+
+    Ok(Json(scripts).into_response())
+}
+
+pub async fn get_platform_scripts(Path(platform): Path<String>) -> Result<Response, ApiError> {
+    let platform = files::platform(&platform).ok_or(ApiError::Client("Unknown platform"))?;
+
+    Ok(Json(list(platform).await?).into_response())
+}
+
+pub async fn get_script(
+    Path((platform, name)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    let file = script_file(&platform, &name)?;
+
+    let (source, enabled) = file
+        .read()
+        .await
+        .map_err(|_| ApiError::Server("Couldn't read script"))?
+        .ok_or(ApiError::Client(SCRIPT_NOT_FOUND))?;
+
+    let response = ScriptResponse {
+        entry: ScriptEntry {
+            platform,
+            name: file.name,
+            enabled,
+        },
+        source,
+    };
+
+    Ok(Json(response).into_response())
+}
+
+/// Creates or updates the script, it's rejected if it doesn't compile.
+pub async fn put_script(
+    State(app_state): State<AppState>,
+    Path((platform, name)): Path<(String, String)>,
+    Json(request): Json<WriteRequest>,
+) -> Result<Response, ApiError> {
+    let file = script_file(&platform, &name)?;
+
+    let compiled = app_state
+        .scripts
+        .current()
+        .compile(std::path::Path::new(&name), &request.source);
+    if let Err(err) = compiled {
+        return Ok(Json(json!({"error": true, "message": format!("{err:#}")})).into_response());
+    }
+
+    let action = file
+        .write(&request.source, audit_log(&app_state))
+        .await
+        .map_err(|_| ApiError::Server("Couldn't write script"))?;
+
+    reload(&app_state, &file, "written").await?;
+
+    Ok(Json(WriteResponse { action }).into_response())
+}
+
+pub async fn post_enable_script(
+    State(app_state): State<AppState>,
+    Path((platform, name)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    set_enabled(app_state, &platform, &name, true).await
+}
+
+pub async fn post_disable_script(
+    State(app_state): State<AppState>,
+    Path((platform, name)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    set_enabled(app_state, &platform, &name, false).await
+}
+
+pub async fn delete_script(
+    State(app_state): State<AppState>,
+    Path((platform, name)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    let file = script_file(&platform, &name)?;
+
+    let deleted = file
+        .delete(audit_log(&app_state))
+        .await
+        .map_err(|_| ApiError::Server("Couldn't delete script"))?;
+    if !deleted {
+        return Err(ApiError::Client(SCRIPT_NOT_FOUND));
+    }
+
+    reload(&app_state, &file, "deleted").await?;
+
+    Ok(Json(WriteResponse {
+        action: AuditAction::Delete,
+    })
+    .into_response())
+}
+
+async fn set_enabled(
+    app_state: AppState,
+    platform: &str,
+    name: &str,
+    enabled: bool,
+) -> Result<Response, ApiError> {
+    let file = script_file(platform, name)?;
+
+    let found = file
+        .set_enabled(enabled, audit_log(&app_state))
+        .await
+        .map_err(|_| ApiError::Server("Couldn't rename script"))?;
+    if !found {
+        return Err(ApiError::Client(SCRIPT_NOT_FOUND));
+    }
+
+    let (action, change) = match enabled {
+        true => (AuditAction::Enable, "enabled"),
+        false => (AuditAction::Disable, "disabled"),
+    };
+    reload(&app_state, &file, change).await?;
+
+    Ok(Json(WriteResponse { action }).into_response())
+}
+
+fn script_file(platform: &str, name: &str) -> Result<ScriptFile, ApiError> {
+    let platform = files::platform(platform).ok_or(ApiError::Client("Unknown platform"))?;
+
+    ScriptFile::new(platform, name).ok_or(ApiError::Client("Invalid script name"))
+}
+
+async fn list(platform: ScriptPlatform) -> Result<Vec<ScriptEntry>, ApiError> {
+    files::list(platform)
+        .await
+        .map_err(|_| ApiError::Server("Couldn't list scripts"))
+}
+
+fn audit_log(app_state: &AppState) -> &std::path::Path {
+    &app_state.configuration.analyzer.scripts.audit_log
+}
+
+/// The watcher would pick the change up as well, but only after a delay.
+async fn reload(app_state: &AppState, file: &ScriptFile, change: &str) -> Result<(), ApiError> {
+    info!(
+        "Script {}/{} was {change} through the admin API",
+        file.platform.directory(),
+        file.name
+    );
+
+    let scripts = app_state.scripts.clone();
+    tokio::task::spawn_blocking(move || scripts.reload())
+        .await
+        .map_err(|_| ApiError::Server("Couldn't reload scripts"))
+}
@@ -1,23 +1,42 @@
-use crate::routes::api::{get_analyzer_details, ApiError};
+use crate::routes::api::detections::{api_detections, ApiDetection};
+use crate::routes::api::{get_analysis, ApiError};
 use crate::AppState;
 use axum::{
     extract::{Path, State},
+    http::HeaderMap,
     response::{IntoResponse, Response},
     Json,
 };
+use serde::Serialize;
+
+/// Bumped whenever a field of [`AllResponse`] changes in a way that breaks clients.
+/// Version 1 were the analyzer details alone.
+const SCHEMA_VERSION: u32 = 2;
+
+/// The analyzer details with the detections of the scripts. The details stay at the top level,
+/// so clients of the first version keep working.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllResponse {
+    schema_version: u32,
+    /// `DynamicAnalyzerDetails`
+    #[serde(flatten)]
+    details: serde_json::Value,
+    detections: Vec<ApiDetection>,
+}
 
 pub async fn get_api_all(
     Path(id): Path<String>,
     State(app_state): State<AppState>,
+    headers: HeaderMap,
 ) -> Result<Response, ApiError> {
-    let lines_limits = app_state.configuration.analyzer.lines_limits.clone();
-    let info = get_analyzer_details(
-        id,
-        &app_state.configuration,
-        lines_limits.plugins,
-        lines_limits.ports,
-    )
-    .await?;
+    let analysis = get_analysis(id, &app_state).await?;
+
+    let response = AllResponse {
+        schema_version: SCHEMA_VERSION,
+        detections: api_detections(&analysis, &headers, &app_state.configuration),
+        details: analysis.details,
+    };
 
-    Ok(Json(info).into_response())
+    Ok(Json(response).into_response())
 }
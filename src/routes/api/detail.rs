@@ -10,9 +10,18 @@ pub struct Response {
     markdown: Option<String>,
 }
 
-pub async fn get_frontend_api_detail(
+/// Markdown of the detail of a detection, which the `detailUrl` of detections points at
+pub async fn get_api_detail(
     Path(detail_id): Path<String>,
 ) -> Result<axum::response::Response, ApiError> {
+    let valid = !detail_id.is_empty()
+        && detail_id
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '_' || char == '-');
+    if !valid {
+        return Err(ApiError::Client("Invalid detail id"));
+    }
+
     let detail_path = CURRENT_DIRECTORY
         .join("details")
         .join(format!("{}.md", detail_id));
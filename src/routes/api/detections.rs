@@ -0,0 +1,68 @@
+use crate::configuration::Settings;
+use crate::middleware::has_valid_api_key;
+use crate::paste::cache::Analysis;
+use crate::routes::api::{get_analysis, ApiError};
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Json,
+};
+use mclog::analyzer::dynamic::Detection;
+use serde::Serialize;
+
+/// Detection as the developer API returns it, with a link to its detail
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiDetection {
+    #[serde(flatten)]
+    detection: Detection,
+    /// Where the markdown of the detail can be fetched
+    detail_url: Option<String>,
+}
+
+impl ApiDetection {
+    fn new(detection: Detection, configuration: &Settings) -> Self {
+        let detail_url = detection.detail.as_ref().map(|detail| {
+            format!(
+                "{}/api/detail/{detail}",
+                configuration.application.public_url.trim_end_matches('/')
+            )
+        });
+
+        Self {
+            detection,
+            detail_url,
+        }
+    }
+}
+
+/// Detections of the analysis sorted by level, private ones only for clients with the API key
+pub fn api_detections(
+    analysis: &Analysis,
+    headers: &HeaderMap,
+    configuration: &Settings,
+) -> Vec<ApiDetection> {
+    let detections = match has_valid_api_key(headers) {
+        true => analysis.all_detections(),
+        false => analysis.detections.clone(),
+    };
+
+    detections
+        .into_iter()
+        .map(|detection| ApiDetection::new(detection, configuration))
+        .collect()
+}
+
+pub async fn get_api_detections(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let analysis = get_analysis(id, &app_state).await?;
+
+    let detections = api_detections(&analysis, &headers, &app_state.configuration);
+
+    Ok(Json(detections).into_response())
+}
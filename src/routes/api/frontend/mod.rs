@@ -1,2 +1 @@
-pub mod detail;
 pub mod paste;
@@ -1,18 +1,22 @@
+use crate::middleware::has_valid_api_key;
 use crate::paste::analyzer::PasteType;
+use crate::paste::cache::RenderedHtml;
+use crate::paste::metadata::{hash, PasteMetadata};
 use crate::paste::Paste;
-use crate::routes::api::{get_analyzer_details, get_paste_lines, ApiError};
-use crate::routes::get::get_paste_type;
+use crate::routes::api::{get_analyzer_details, load_analysis, read_paste_lines, ApiError};
+use crate::routes::get::{
+    burn, delete_burned, get_paste_metadata, unburn, was_burned, ALREADY_VIEWED,
+};
+use crate::scripts::trace::ScriptTrace;
 use crate::AppState;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
-use mclog::analyzer::dynamic::chunks::Captures;
-use mclog::analyzer::dynamic::{Detection, DynamicAnalyzer, ScriptPlatform};
-use mclog::analyzer::{DynamicAnalyzerDetails, Platform};
+use mclog::analyzer::dynamic::Detection;
 use mclog::parser::parser::Parser;
-use rhai::{Dynamic, Scope};
-use serde::Serialize;
-use tracing::{error, warn};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -27,61 +31,167 @@ pub struct LogResponse {
     content: String,
     paste_type: PasteType,
     version: String,
-    platform: Platform,
+    platform: serde_json::Value,
     detections: Vec<Detection>,
+    /// Only in debug mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace: Option<Vec<ScriptTrace>>,
+}
+
+#[derive(Deserialize)]
+pub struct PasteQuery {
+    /// `?debug=1` adds the trace of every script, admins only
+    debug: Option<String>,
+}
+
+impl PasteQuery {
+    fn debug(&self) -> bool {
+        matches!(self.debug.as_deref(), Some("1" | "true"))
+    }
 }
 
 pub async fn get_paste(
     State(app_state): State<AppState>,
     Path(id): Path<String>,
+    Query(query): Query<PasteQuery>,
+    headers: HeaderMap,
 ) -> Result<Response, ApiError> {
-    let paste = Paste::new(id.clone(), app_state.configuration.clone());
-    let paste_type = get_paste_type(id.clone(), &paste, app_state.clone())
+    let debug = query.debug();
+    if debug && !app_state.configuration.api.no_auth && !has_valid_api_key(&headers) {
+        return Err(ApiError::Client("Debug mode requires the admin API key"));
+    }
+
+    let paste = Paste::new(id.clone(), app_state.store.clone());
+
+    if was_burned(&id, &app_state)
         .await
-        .map_err(|_| ApiError::Server("Couldn't get PasteType"))?;
+        .map_err(|_| ApiError::Server("Couldn't get paste metadata"))?
+    {
+        return Err(ApiError::Client(ALREADY_VIEWED));
+    }
+
+    let metadata = get_paste_metadata(&id, &app_state)
+        .await
+        .map_err(|_| ApiError::Server("Couldn't get PasteType"))?
+        .ok_or(ApiError::Client("Paste not found"))?;
+
+    let burn_after_read = metadata.burn_after_read;
+    if burn_after_read {
+        let claimed = burn(&id, &metadata, &app_state)
+            .await
+            .map_err(|_| ApiError::Server("Couldn't burn paste"))?;
+        if !claimed {
+            return Err(ApiError::Client(ALREADY_VIEWED));
+        }
+    }
+
+    // burned pastes are never shown again, so they're neither cached nor revalidated
+    let cacheable = Some(&metadata).filter(|metadata| {
+        !burn_after_read && !metadata.is_legacy() && matches!(metadata.paste_type, PasteType::Log)
+    });
 
-    let limits = app_state.configuration.analyzer.lines_limits.clone();
+    let etag = match cacheable.filter(|_| !debug) {
+        Some(metadata) => Some(etag(metadata, &app_state).await?),
+        None => None,
+    };
 
+    if let Some(etag) = &etag {
+        if is_fresh(&headers, etag) {
+            return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag.as_str())]).into_response());
+        }
+    }
+
+    let response = paste_response(
+        id.clone(),
+        &paste,
+        metadata.paste_type,
+        cacheable,
+        debug,
+        &app_state,
+    )
+    .await
+    .map(|mut response| {
+        if let Some(etag) = etag.and_then(|etag| HeaderValue::from_str(&etag).ok()) {
+            let headers = response.headers_mut();
+            headers.insert(header::ETAG, etag);
+            headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        }
+        response
+    });
+
+    if burn_after_read {
+        match &response {
+            Ok(_) => delete_burned(&id, &metadata, &app_state)
+                .await
+                .map_err(|_| ApiError::Server("Couldn't delete burned paste"))?,
+            Err(_) => unburn(&id, &metadata, &app_state)
+                .await
+                .map_err(|_| ApiError::Server("Couldn't restore burned paste"))?,
+        }
+    }
+
+    response
+}
+
+/// The response changes when the log, the highlighting delimiters or the scripts change.
+async fn etag(metadata: &PasteMetadata, app_state: &AppState) -> Result<String, ApiError> {
+    let scripts = app_state.scripts.current();
+    let tag = RenderedHtml::tag(&metadata.content_hash, &app_state.configuration);
+
+    Ok(format!(
+        "\"{}\"",
+        &hash(format!("{tag}{}", scripts.version()).as_bytes())[..32]
+    ))
+}
+
+fn is_fresh(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().trim_start_matches("W/"))
+        .any(|value| value == etag || value == "*")
+}
+
+/// `cacheable` is the metadata of a paste whose rendered HTML can be cached.
+async fn paste_response(
+    id: String,
+    paste: &Paste,
+    paste_type: PasteType,
+    cacheable: Option<&PasteMetadata>,
+    debug: bool,
+    app_state: &AppState,
+) -> Result<Response, ApiError> {
     if let PasteType::Log = paste_type {
-        let lines = get_paste_lines(id.clone(), &app_state.configuration, 50_000).await?;
-        let parser = Parser::new(
-            lines,
-            app_state
-                .configuration
-                .analyzer
-                .custom_highlighting_delimiters
-                .clone(),
-        );
-
-        let html_as_bytes = parsed_html_log(parser);
-
-        let details = get_analyzer_details(
-            id.clone(),
-            &app_state.configuration,
-            limits.plugins,
-            limits.ports,
-        )
-        .await?;
+        let html = get_html(&id, cacheable, app_state).await?;
+
+        let trace = match debug {
+            true => Some(get_trace(id.clone(), app_state).await?),
+            false => None,
+        };
 
-        let html = String::from_utf8_lossy(&html_as_bytes).to_string();
+        // burn-after-read pastes were claimed by `get_paste` already
+        let analysis = load_analysis(id, app_state).await?;
 
         let response = LogResponse {
-            version: details
-                .version
-                .clone()
-                .unwrap_or_else(|| "Unknown".to_string()),
-            platform: details.platform,
-            detections: get_detections(details.clone()),
+            version: analysis.details["version"]
+                .as_str()
+                .unwrap_or("Unknown")
+                .to_string(),
+            platform: analysis.details["platform"].clone(),
+            detections: analysis.detections,
+            trace,
             content: html,
             paste_type,
         };
 
         Ok(Json(response).into_response())
     } else {
-        let content =
-            tokio::fs::read_to_string(app_state.configuration.storage.directory.join(id.clone()))
-                .await
-                .map_err(|_| ApiError::Client("Couldn't read file to String"))?;
+        let content = paste
+            .content()
+            .await
+            .map_err(|_| ApiError::Client("Couldn't read file to String"))?;
 
         let response = NoLogResponse {
             content,
@@ -92,127 +202,62 @@ pub async fn get_paste(
     }
 }
 
-fn parsed_html_log(parser: Parser) -> Vec<u8> {
-    let html = parser.html();
+async fn get_html(
+    id: &str,
+    cacheable: Option<&PasteMetadata>,
+    app_state: &AppState,
+) -> Result<String, ApiError> {
+    let pool = &app_state.redis_state.pool;
+    let configuration = &app_state.configuration;
 
-    html.iter().flat_map(|entry| entry.bytes()).collect()
-}
+    let tag = cacheable.map(|metadata| RenderedHtml::tag(&metadata.content_hash, configuration));
 
-fn get_detections(dad: DynamicAnalyzerDetails) -> Vec<Detection> {
-    let engine = DynamicAnalyzer::default();
-    let mut scripts = Vec::new();
-    scripts.append(&mut engine.scripts(ScriptPlatform::Global));
-
-    match dad.platform {
-        Platform::Vanilla => {}
-        Platform::CraftBukkit
-        | Platform::Spigot
-        | Platform::Paper
-        | Platform::Pufferfish
-        | Platform::Purpur => scripts.append(&mut engine.scripts(ScriptPlatform::Bukkit)),
-        Platform::Fabric => scripts.append(&mut engine.scripts(ScriptPlatform::Fabric)),
-        Platform::Forge => scripts.append(&mut engine.scripts(ScriptPlatform::Forge)),
-        Platform::BungeeCord | Platform::Waterfall => {
-            scripts.append(&mut engine.scripts(ScriptPlatform::BungeeCord))
+    if let Some(tag) = &tag {
+        match RenderedHtml::load(pool, id, tag).await {
+            Ok(Some(html)) => return Ok(html),
+            Ok(None) => {}
+            Err(err) => warn!("Couldn't load rendered HTML of '{id}': {err}"),
         }
-        Platform::Velocity => scripts.append(&mut engine.scripts(ScriptPlatform::Velocity)),
     }
 
-    if !dad.is_proxy {
-        scripts.append(&mut engine.scripts(ScriptPlatform::NoProxy));
-    }
+    let lines = read_paste_lines(id.to_string(), app_state, 50_000).await?;
+    let delimiters = configuration
+        .analyzer
+        .custom_highlighting_delimiters
+        .clone();
 
-    let mut scope = Scope::new();
-    scope.push_constant("dad", dad.clone());
-
-    let mut detections = Vec::new();
-
-    for script in scripts {
-        let result = match engine
-            .engine
-            .eval_ast_with_scope::<Dynamic>(&mut scope, &script.ast)
-        {
-            Ok(return_code) => return_code,
-            Err(err) => {
-                error!("{} in file {}", err, script.file);
-                Dynamic::UNIT
-            }
-        };
+    let html_as_bytes = app_state
+        .workers
+        .run(move || parsed_html_log(Parser::new(lines, delimiters)))
+        .await?;
 
-        if result.is_unit() {
-            continue;
-        };
+    let html = String::from_utf8_lossy(&html_as_bytes).to_string();
 
-        if result.is_string() {
-            let result = result.to_string();
-
-            match script.detection.get(&result) {
-                None => {
-                    error!(
-                        "Error while obtaining detection info for {} from file {}",
-                        result, script.file
-                    );
-                    continue;
-                }
-                Some(det) => {
-                    let det = det.to_owned();
-
-                    if det.private.is_some_and(|is_private| is_private) {
-                        continue;
-                    }
-                    detections.push(det.clone())
-                }
-            }
-        } else if let Some(guard) = result.read_lock::<Vec<Captures>>() {
-            let results: Vec<Captures> = guard.to_owned();
-
-            for result in results {
-                match script.detection.get(&result.identifier) {
-                    None => {
-                        error!(
-                            "Error while obtaining detection info for {:?} from file {}",
-                            result, script.file
-                        );
-                        continue;
-                    }
-                    Some(det) => {
-                        let mut det = det.to_owned();
-
-                        for capture in result.captures.iter() {
-                            det.header = det.header.replace(
-                                &format!("{{{}}}", capture.0),
-                                result
-                                    .captures
-                                    .get(capture.0)
-                                    .expect("Capture doesn't exist, this should not happen"),
-                            );
-                        }
-
-                        for (idx, _) in det.clone().solutions.into_iter().enumerate() {
-                            for capture in result.captures.iter() {
-                                det.solutions[idx] = det.clone().solutions[idx].clone().replace(
-                                    &format!("{{{}}}", capture.0),
-                                    result
-                                        .captures
-                                        .get(capture.0)
-                                        .expect("Capture doesn't exist, this should not happen"),
-                                );
-                            }
-                        }
-
-                        if det.private.is_some_and(|is_private| is_private) {
-                            continue;
-                        }
-                        detections.push(det.clone())
-                    }
-                }
-            }
-        } else {
-            warn!("Unexpected result type for {:?}, skipping..", result);
-            continue;
+    if let (Some(tag), Some(metadata)) = (&tag, cacheable) {
+        if let Err(err) = RenderedHtml::save(pool, id, tag, &html, metadata.expires_at).await {
+            warn!("Couldn't cache rendered HTML of '{id}': {err}");
         }
     }
-    detections.sort_by_key(|item| item.level);
 
-    detections
+    Ok(html)
+}
+
+/// Runs the scripts again, the cached analysis doesn't tell what happened in them.
+async fn get_trace(id: String, app_state: &AppState) -> Result<Vec<ScriptTrace>, ApiError> {
+    let limits = &app_state.configuration.analyzer.lines_limits;
+    let details = get_analyzer_details(id, app_state, limits.plugins, limits.ports).await?;
+
+    let scripts = app_state.scripts.current();
+    let trace = app_state
+        .workers
+        .run(move || scripts.trace(details))
+        .await?;
+
+    Ok(trace)
+}
+
+fn parsed_html_log(parser: Parser) -> Vec<u8> {
+    let html = parser.html();
+
+    html.iter().flat_map(|entry| entry.bytes()).collect()
 }
@@ -11,11 +11,10 @@ pub async fn get_api_leaks(
     Path(id): Path<String>,
     State(app_state): State<AppState>,
 ) -> Result<Response, ApiError> {
-    let configuration = app_state.configuration;
     let lines = get_paste_lines(
         id,
-        &configuration,
-        configuration.analyzer.lines_limits.plugins,
+        &app_state,
+        app_state.configuration.analyzer.lines_limits.plugins,
     )
     .await?;
 
@@ -0,0 +1,60 @@
+use crate::paste::analyzer::PasteType;
+use crate::paste::metadata::{BundleMember, PasteMetadata};
+use crate::routes::api::ApiError;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+/// Metadata as anyone can see it, without who uploaded the paste or the deletion token hash
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiMetadata {
+    paste_type: PasteType,
+    size: u64,
+    line_count: u64,
+    created_at: u64,
+    expires_at: Option<u64>,
+    filename: Option<String>,
+    archive: Option<String>,
+    platform: Option<String>,
+    version: Option<String>,
+    content_hash: String,
+    burn_after_read: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    members: Vec<BundleMember>,
+}
+
+impl From<PasteMetadata> for ApiMetadata {
+    fn from(metadata: PasteMetadata) -> Self {
+        Self {
+            paste_type: metadata.paste_type,
+            size: metadata.size,
+            line_count: metadata.line_count,
+            created_at: metadata.created_at,
+            expires_at: metadata.expires_at,
+            filename: metadata.filename,
+            archive: metadata.archive,
+            platform: metadata.platform,
+            version: metadata.version,
+            content_hash: metadata.content_hash,
+            burn_after_read: metadata.burn_after_read,
+            members: metadata.members,
+        }
+    }
+}
+
+pub async fn get_api_meta(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+) -> Result<Response, ApiError> {
+    let metadata = PasteMetadata::load(&app_state.redis_state.pool, &id)
+        .await
+        .map_err(|_| ApiError::Server("Couldn't get paste metadata"))?
+        .ok_or(ApiError::Client("Paste not found"))?;
+
+    Ok(Json(ApiMetadata::from(metadata)).into_response())
+}
@@ -1,15 +1,25 @@
-use crate::configuration::Settings;
+use crate::paste::cache::Analysis;
+use crate::paste::metadata::PasteMetadata;
 use crate::paste::Paste;
+use crate::routes::get::{was_burned, ALREADY_VIEWED};
+use crate::worker::{WorkerError, RETRY_AFTER_IN_SECONDS};
+use crate::AppState;
+use axum::http::{header, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
 use mclog::analyzer::{Analyzer, DynamicAnalyzerDetails};
 use mclog::log::Log;
 use serde::Serialize;
-use tracing::log::info;
+use tracing::log::{info, warn};
 
+pub mod admin;
 pub mod all;
+pub mod detail;
+pub mod detections;
 pub mod frontend;
 pub mod leaks;
+pub mod meta;
+pub mod paste;
 pub mod plugins;
 pub mod ports;
 pub mod upload;
@@ -18,6 +28,14 @@ pub mod upload;
 pub enum ApiError {
     Client(&'static str),
     Server(&'static str),
+    /// Credentials are missing, responds with 401
+    Unauthorized(&'static str),
+    /// Credentials are wrong, responds with 403
+    Forbidden(&'static str),
+    /// Responds with 404
+    NotFound(&'static str),
+    /// The server is overloaded, responds with 503 and `Retry-After`
+    Unavailable(&'static str),
 }
 
 #[derive(Serialize)]
@@ -28,25 +46,80 @@ struct ApiResponse {
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        let err = match self {
-            ApiError::Client(err) => err,
-            ApiError::Server(err) => err,
+        let (err, status) = match self {
+            ApiError::Client(err) => (err, StatusCode::OK),
+            ApiError::Server(err) => (err, StatusCode::OK),
+            ApiError::Unauthorized(err) => (err, StatusCode::UNAUTHORIZED),
+            ApiError::Forbidden(err) => (err, StatusCode::FORBIDDEN),
+            ApiError::NotFound(err) => (err, StatusCode::NOT_FOUND),
+            ApiError::Unavailable(err) => (err, StatusCode::SERVICE_UNAVAILABLE),
         };
         info!("API error occurred: '{}'", err);
-        Json(ApiResponse {
+        let response = Json(ApiResponse {
             error: true,
             message: Some(err),
-        })
-        .into_response()
+        });
+
+        if status == StatusCode::SERVICE_UNAVAILABLE {
+            return (
+                status,
+                [(header::RETRY_AFTER, RETRY_AFTER_IN_SECONDS.to_string())],
+                response,
+            )
+                .into_response();
+        }
+
+        (status, response).into_response()
+    }
+}
+
+impl From<WorkerError> for ApiError {
+    fn from(err: WorkerError) -> Self {
+        match err {
+            WorkerError::Busy => ApiError::Unavailable("Server is busy, try again later"),
+            WorkerError::TimedOut => ApiError::Server("Analysis took too long"),
+            WorkerError::Panicked => ApiError::Server("Analysis failed"),
+        }
+    }
+}
+
+/// Burn-after-read pastes can only be viewed through the paste page, which burns them.
+/// Anything derived from their content is refused as well.
+async fn ensure_not_burn_after_read(id: &str, app_state: &AppState) -> Result<(), ApiError> {
+    let burned = was_burned(id, app_state)
+        .await
+        .map_err(|_| ApiError::Server("Couldn't get paste metadata"))?;
+    if burned {
+        return Err(ApiError::Client(ALREADY_VIEWED));
+    }
+
+    let metadata = PasteMetadata::load(&app_state.redis_state.pool, id)
+        .await
+        .map_err(|_| ApiError::Server("Couldn't get paste metadata"))?;
+    if metadata.is_some_and(|metadata| metadata.burn_after_read) {
+        return Err(ApiError::Client("Paste can only be viewed once"));
     }
+
+    Ok(())
 }
 
 async fn get_paste_lines(
     id: String,
-    configuration: &Settings,
+    app_state: &AppState,
     limit: usize,
 ) -> Result<Vec<String>, ApiError> {
-    let paste = Paste::new(id, configuration.clone());
+    ensure_not_burn_after_read(&id, app_state).await?;
+
+    read_paste_lines(id, app_state, limit).await
+}
+
+/// Doesn't check for burn-after-read, see [`get_paste_lines`].
+async fn read_paste_lines(
+    id: String,
+    app_state: &AppState,
+    limit: usize,
+) -> Result<Vec<String>, ApiError> {
+    let paste = Paste::new(id, app_state.store.clone());
     let lines = match paste.lines().await {
         Ok(lines) => lines,
         Err(_err) => return Err(ApiError::Server("Couldn't get paste lines")),
@@ -58,14 +131,107 @@ async fn get_paste_lines(
     Ok(lines)
 }
 
+/// Doesn't check for burn-after-read, the callers either did or are admins.
 async fn get_analyzer_details(
     id: String,
-    configuration: &Settings,
+    app_state: &AppState,
     plugins_limit: usize,
     ports_limit: usize,
 ) -> Result<DynamicAnalyzerDetails, ApiError> {
-    let lines =
-        get_paste_lines(id, configuration, std::cmp::max(plugins_limit, ports_limit)).await?;
+    let lines = read_paste_lines(
+        id.clone(),
+        app_state,
+        std::cmp::max(plugins_limit, ports_limit),
+    )
+    .await?;
+
+    let mut details = app_state
+        .workers
+        .run(move || Analyzer::new(&lines).build(plugins_limit, ports_limit))
+        .await?;
+
+    if ports_limit > 0 {
+        for port in get_bundle_ports(&id, app_state).await {
+            if !details.ports.contains(&port) {
+                details.ports.push(port);
+            }
+        }
+    }
+
+    Ok(details)
+}
+
+/// Analyzer details and detections of a log, cached until the paste expires
+/// or the scripts change.
+async fn get_analysis(id: String, app_state: &AppState) -> Result<Analysis, ApiError> {
+    ensure_not_burn_after_read(&id, app_state).await?;
+
+    load_analysis(id, app_state).await
+}
+
+/// Doesn't check for burn-after-read, see [`get_analysis`].
+async fn load_analysis(id: String, app_state: &AppState) -> Result<Analysis, ApiError> {
+    let pool = &app_state.redis_state.pool;
+
+    let scripts = app_state.scripts.current();
+
+    match Analysis::load(pool, &id, scripts.version()).await {
+        Ok(Some(analysis)) => return Ok(analysis),
+        Ok(None) => {}
+        Err(err) => warn!("Couldn't load cached analysis of '{id}': {err}"),
+    }
+
+    let limits = &app_state.configuration.analyzer.lines_limits;
+    let details = get_analyzer_details(id.clone(), app_state, limits.plugins, limits.ports).await?;
+
+    let details_json = serde_json::to_value(&details)
+        .map_err(|_| ApiError::Server("Couldn't serialize analyzer details"))?;
+    let detections = app_state
+        .workers
+        .run({
+            let scripts = scripts.clone();
+            move || scripts.detections(details)
+        })
+        .await?;
+
+    let analysis = Analysis::new(scripts.version().to_string(), details_json, detections);
+
+    // burned pastes have no metadata anymore, there's no point in caching them
+    if let Ok(Some(metadata)) = PasteMetadata::load(pool, &id).await {
+        if let Err(err) = analysis.save(pool, &id, metadata.expires_at).await {
+            warn!("Couldn't cache analysis of '{id}': {err}");
+        }
+    }
+
+    Ok(analysis)
+}
+
+/// Bundles that include `server.properties` know the server port even when the log
+/// doesn't mention it.
+async fn get_bundle_ports(id: &str, app_state: &AppState) -> Vec<u16> {
+    let Ok(Some(metadata)) = PasteMetadata::load(&app_state.redis_state.pool, id).await else {
+        return Vec::new();
+    };
+
+    let Some(properties) = metadata
+        .members
+        .into_iter()
+        .find(|member| member.name.ends_with("server.properties"))
+    else {
+        return Vec::new();
+    };
+
+    let content = match Paste::new(properties.id, app_state.store.clone())
+        .content()
+        .await
+    {
+        Ok(content) => content,
+        Err(_err) => return Vec::new(),
+    };
 
-    Ok(Analyzer::new(&lines).build(plugins_limit, ports_limit))
+    content
+        .lines()
+        .filter_map(|line| line.strip_prefix("server-port="))
+        .filter_map(|port| port.trim().parse().ok())
+        .collect()
 }
@@ -0,0 +1,81 @@
+use crate::middleware::has_valid_api_key;
+use crate::paste::cache;
+use crate::paste::metadata::{hash, PasteMetadata};
+use crate::paste::Paste;
+use crate::routes::api::{ApiError, ApiResponse};
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Json,
+};
+use fred::interfaces::KeysInterface;
+use tracing::warn;
+
+/// Deletes a paste, or all files of a bundle. Requires either the deletion token returned
+/// on upload in the `X-Deletion-Token` header or the admin `X-API-KEY`.
+pub async fn delete_api_paste(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let pool = &app_state.redis_state.pool;
+
+    let metadata = PasteMetadata::load(pool, &id)
+        .await
+        .map_err(|_| ApiError::Server("Couldn't get paste metadata"))?
+        .ok_or(ApiError::NotFound("Paste not found"))?;
+
+    if !has_valid_api_key(&headers) {
+        let token = headers
+            .get("x-deletion-token")
+            .and_then(|token| token.to_str().ok())
+            .ok_or(ApiError::Unauthorized("No 'X-Deletion-Token' header found"))?;
+
+        if metadata.deletion_token.as_deref() != Some(hash(token.as_bytes()).as_str()) {
+            return Err(ApiError::Forbidden("Wrong deletion token"));
+        }
+    }
+
+    let _: () = pool
+        .del(&id)
+        .await
+        .map_err(|_| ApiError::Server("Couldn't delete paste metadata"))?;
+    cache::delete(pool, &id)
+        .await
+        .map_err(|_| ApiError::Server("Couldn't delete cached paste"))?;
+
+    Paste::new(id.clone(), app_state.store.clone())
+        .delete()
+        .await
+        .map_err(|_| ApiError::Server("Couldn't delete paste"))?;
+
+    for member in metadata
+        .members
+        .into_iter()
+        .filter(|member| member.id != id)
+    {
+        let _: () = pool
+            .del(&member.id)
+            .await
+            .map_err(|_| ApiError::Server("Couldn't delete paste metadata"))?;
+        cache::delete(pool, &member.id)
+            .await
+            .map_err(|_| ApiError::Server("Couldn't delete cached paste"))?;
+
+        // burn-after-read members may be gone already
+        if let Err(err) = Paste::new(member.id, app_state.store.clone())
+            .delete()
+            .await
+        {
+            warn!("Couldn't delete bundle member of '{id}': {err}");
+        }
+    }
+
+    Ok(Json(ApiResponse {
+        error: false,
+        message: Some("Paste deleted"),
+    })
+    .into_response())
+}
@@ -1,4 +1,4 @@
-use crate::routes::api::{get_analyzer_details, ApiError};
+use crate::routes::api::{get_analysis, ApiError};
 use crate::AppState;
 use axum::{
     extract::{Path, State},
@@ -10,15 +10,7 @@ pub async fn get_api_plugins(
     Path(id): Path<String>,
     State(app_state): State<AppState>,
 ) -> Result<Response, ApiError> {
-    let configuration = app_state.configuration;
+    let analysis = get_analysis(id, &app_state).await?;
 
-    let info = get_analyzer_details(
-        id,
-        &configuration,
-        configuration.analyzer.lines_limits.plugins,
-        0,
-    )
-    .await?;
-
-    Ok(Json(info.plugins).into_response())
+    Ok(Json(&analysis.details["plugins"]).into_response())
 }
@@ -1,4 +1,4 @@
-use crate::routes::api::{get_analyzer_details, ApiError};
+use crate::routes::api::{get_analysis, ApiError};
 use crate::AppState;
 use axum::response::IntoResponse;
 use axum::{
@@ -11,15 +11,7 @@ pub async fn get_api_ports(
     Path(id): Path<String>,
     State(app_state): State<AppState>,
 ) -> Result<Response, ApiError> {
-    let configuration = app_state.configuration;
+    let analysis = get_analysis(id, &app_state).await?;
 
-    let info = get_analyzer_details(
-        id,
-        &configuration,
-        0,
-        configuration.analyzer.lines_limits.ports,
-    )
-    .await?;
-
-    Ok(Json(info.ports).into_response())
+    Ok(Json(&analysis.details["ports"]).into_response())
 }
@@ -1,49 +1,164 @@
-use crate::paste::analyzer::PasteAnalyzer;
+use crate::configuration::Settings;
+use crate::paste::analyzer::PasteType;
 use crate::routes::api::ApiError;
-use crate::routes::post::{gen_id, get_expiration};
+use crate::routes::post::{finish_upload, gen_id, stream_to_file, Upload, UploadOptions};
 use crate::AppState;
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
-use fred::interfaces::KeysInterface;
+use axum::{
+    body::Bytes,
+    extract::{rejection::JsonRejection, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use futures::stream;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncWriteExt;
+use std::convert::Infallible;
+
+/// Everything but the unreserved characters of RFC 3986 is encoded in a path segment
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Request {
     content: String,
+    #[serde(default)]
+    encoding: Encoding,
+    filename: Option<String>,
+    /// e.g. `1h`, `1d`, `7d` or `never`
+    expires: Option<String>,
+    #[serde(default)]
+    burn_after_read: bool,
+}
+
+/// Gzip and zip archives can't be sent as plain text, they have to be base64 encoded.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    #[default]
+    Text,
+    Base64,
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Response {
     id: String,
+    paste_type: PasteType,
+    url: String,
+    raw_url: String,
+    filename: Option<String>,
+    /// Unix timestamp, `None` if the paste never expires
+    expires_at: Option<u64>,
+    /// Send it in the `X-Deletion-Token` header to `DELETE /api/paste/:id`
+    deletion_token: String,
+    /// Other pastes created from the same zip archive
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    extracted: Vec<Response>,
+    /// Files of a bundle, including the paste itself
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files: Vec<BundleFile>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BundleFile {
+    name: String,
+    paste_type: PasteType,
+    raw_url: String,
+}
+
+impl Response {
+    pub fn new(uploads: Vec<Upload>, configuration: &Settings) -> Self {
+        let public_url = configuration.application.public_url.trim_end_matches('/');
+
+        let mut uploads = uploads
+            .into_iter()
+            .map(|upload| Self::single(upload, public_url));
+
+        let mut response = uploads.next().expect("upload without pastes");
+        response.extracted = uploads.collect();
+
+        response
+    }
+
+    fn single(upload: Upload, public_url: &str) -> Self {
+        let id = upload.id;
+
+        let files = upload
+            .metadata
+            .members
+            .into_iter()
+            .map(|member| BundleFile {
+                raw_url: format!("{public_url}/raw/{id}/{}", encode_path(&member.name)),
+                name: member.name,
+                paste_type: member.paste_type,
+            })
+            .collect();
+
+        Self {
+            url: format!("{public_url}/{id}"),
+            raw_url: format!("{public_url}/raw/{id}"),
+            id,
+            paste_type: upload.metadata.paste_type,
+            filename: upload.metadata.filename,
+            expires_at: upload.metadata.expires_at,
+            deletion_token: upload.deletion_token,
+            extracted: Vec::new(),
+            files,
+        }
+    }
+}
+
+/// Member names are paths like `plugins/Essentials/config.yml`, every segment is encoded
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
 }
 
 pub async fn post_api_upload(
     State(app_state): State<AppState>,
-    Json(request): Json<Request>,
+    headers: HeaderMap,
+    request: Result<Json<Request>, JsonRejection>,
 ) -> Result<axum::response::Response, ApiError> {
+    let Json(request) = request.map_err(|_| ApiError::Client("Invalid upload request"))?;
+
+    if request.content.is_empty() {
+        return Err(ApiError::Client("Paste content is empty"));
+    }
+
+    if request.content.len() > app_state.configuration.paste.size_limit {
+        return Err(ApiError::Client("Paste content is too large"));
+    }
+
+    let content = match request.encoding {
+        Encoding::Text => request.content.into_bytes(),
+        Encoding::Base64 => BASE64_STANDARD
+            .decode(request.content)
+            .map_err(|_| ApiError::Client("Content is not valid base64"))?,
+    };
+
     let id = gen_id(app_state.configuration.storage.id_length);
-    let paste_dir = app_state.configuration.storage.directory.join(id.clone());
-
-    let mut file = tokio::fs::File::create(paste_dir).await.unwrap();
-    file.write_all(request.content.as_bytes()).await.unwrap();
-
-    let paste_analyzer = PasteAnalyzer::new();
-    let paste_type = paste_analyzer.paste_type(request.content.as_bytes());
-
-    let _: () = app_state
-        .redis_state
-        .pool
-        .set(
-            id.clone(),
-            paste_type,
-            get_expiration(&app_state.configuration),
-            None,
-            false,
-        )
-        .await
-        .unwrap();
-
-    Ok((StatusCode::OK, Json(Response { id })).into_response())
+    let body = stream::once(async { Ok::<_, Infallible>(Bytes::from(content)) });
+
+    let files = stream_to_file(id, request.filename, body, &app_state).await?;
+
+    let options = UploadOptions {
+        filename: None,
+        expires: request.expires,
+        burn_after_read: request.burn_after_read,
+    };
+
+    let uploads = finish_upload(&app_state, files, &headers, &options).await?;
+
+    let response = Response::new(uploads, &app_state.configuration);
+
+    Ok((StatusCode::OK, Json(response)).into_response())
 }
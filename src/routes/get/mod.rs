@@ -1,34 +1,122 @@
-use crate::paste::analyzer::PasteType;
+use crate::paste::metadata::PasteMetadata;
 use crate::paste::Paste;
 use crate::{AppError, AppState};
-use anyhow::anyhow;
-use fred::interfaces::KeysInterface;
+use fred::interfaces::{KeysInterface, LuaInterface};
 
 pub mod raw;
 
-pub async fn get_paste_type(
-    id: String,
-    paste: &Paste,
-    app_state: AppState,
-) -> Result<PasteType, AppError> {
-    let paste_type: Option<String> = app_state
+pub const ALREADY_VIEWED: &str = "Paste was already viewed";
+
+/// `KEYS`: metadata key, burned key. `ARGV`: value of the burned key, when it expires or `""`.
+/// Replaces the metadata with the burned key at once, so every request sees one of them.
+const BURN_SCRIPT: &str = r#"
+if redis.call('DEL', KEYS[1]) == 0 then
+    return 0
+end
+if ARGV[2] == '' then
+    redis.call('SET', KEYS[2], ARGV[1])
+else
+    redis.call('SET', KEYS[2], ARGV[1], 'EXAT', ARGV[2])
+end
+return 1
+"#;
+
+/// `None` if the paste doesn't exist. Stored pastes without metadata are left to the
+/// [`Sweeper`](crate::sweeper::Sweeper), which rebuilds or removes it.
+pub async fn get_paste_metadata(
+    id: &str,
+    app_state: &AppState,
+) -> Result<Option<PasteMetadata>, AppError> {
+    Ok(PasteMetadata::load(&app_state.redis_state.pool, id).await?)
+}
+
+/// Burn-after-read pastes leave this key behind, so later requests can tell
+/// the paste was viewed instead of failing like it never existed.
+fn burned_key(id: &str) -> String {
+    format!("{id}:burned")
+}
+
+pub async fn was_burned(id: &str, app_state: &AppState) -> Result<bool, AppError> {
+    Ok(app_state.redis_state.pool.exists(burned_key(id)).await?)
+}
+
+/// Claims a burn-after-read paste for the current request by removing its metadata.
+/// Returns `false` if another request already claimed it. The caller is responsible for
+/// calling [`delete_burned`] once it's shown, or [`unburn`] if that failed.
+pub async fn burn(
+    id: &str,
+    metadata: &PasteMetadata,
+    app_state: &AppState,
+) -> Result<bool, AppError> {
+    // only one request gets to delete the metadata
+    let claimed: i64 = app_state
         .redis_state
         .pool
-        .get(id.clone())
-        .await
-        .unwrap_or(None);
-
-    let paste_type = match paste_type {
-        Some(pt) => PasteType::from(pt),
-        None => {
-            if paste.file_path().exists() {
-                tokio::fs::remove_file(paste.file_path()).await?;
-                return Err(AppError(anyhow!("Couldn't remove {id}")));
-            } else {
-                PasteType::Other
-            }
+        .eval(
+            BURN_SCRIPT,
+            vec![id.to_string(), burned_key(id)],
+            vec![metadata.created_at.to_string(), expires_at(metadata)],
+        )
+        .await?;
+
+    Ok(claimed == 1)
+}
+
+/// Deletes the content of a paste claimed by [`burn`] once it was shown. The other files of
+/// a bundle are burned with it, they can't be reached without its metadata anymore.
+pub async fn delete_burned(
+    id: &str,
+    metadata: &PasteMetadata,
+    app_state: &AppState,
+) -> Result<(), AppError> {
+    Paste::new(id.to_string(), app_state.store.clone())
+        .delete()
+        .await?;
+
+    for member in metadata.members.iter().filter(|member| member.id != id) {
+        // members were uploaded together with the bundle and expire with it, a member that
+        // was viewed on its own is burned already
+        if burn(&member.id, metadata, app_state).await? {
+            Paste::new(member.id.clone(), app_state.store.clone())
+                .delete()
+                .await?;
         }
-    };
+    }
+
+    Ok(())
+}
+
+/// `KEYS`: metadata key, burned key. `ARGV`: metadata, when it expires or `""`
+const UNBURN_SCRIPT: &str = r#"
+if ARGV[2] == '' then
+    redis.call('SET', KEYS[1], ARGV[1])
+else
+    redis.call('SET', KEYS[1], ARGV[1], 'EXAT', ARGV[2])
+end
+redis.call('DEL', KEYS[2])
+"#;
+
+/// Undoes [`burn`] when the paste couldn't be shown, so it can still be viewed once.
+pub async fn unburn(
+    id: &str,
+    metadata: &PasteMetadata,
+    app_state: &AppState,
+) -> Result<(), AppError> {
+    let value = serde_json::to_string(metadata).expect("PasteMetadata is always serializable");
+
+    Ok(app_state
+        .redis_state
+        .pool
+        .eval(
+            UNBURN_SCRIPT,
+            vec![id.to_string(), burned_key(id)],
+            vec![value, expires_at(metadata)],
+        )
+        .await?)
+}
 
-    Ok(paste_type)
+fn expires_at(metadata: &PasteMetadata) -> String {
+    metadata
+        .expires_at
+        .map_or_else(String::new, |expires_at| expires_at.to_string())
 }
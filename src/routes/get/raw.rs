@@ -1,8 +1,12 @@
 use crate::paste::analyzer::PasteType;
+use crate::paste::metadata::PasteMetadata;
 use crate::paste::Paste;
-use crate::routes::get::get_paste_type;
+use crate::routes::get::{
+    burn, delete_burned, get_paste_metadata, unburn, was_burned, ALREADY_VIEWED,
+};
 use crate::{AppError, AppState};
 use axum::extract::{Path, State};
+use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use mclog::log::Log;
 
@@ -10,10 +14,54 @@ pub async fn get_raw(
     Path(id): Path<String>,
     State(app_state): State<AppState>,
 ) -> Result<Response, AppError> {
-    let paste = Paste::new(id.clone(), app_state.configuration.clone());
+    let paste = Paste::new(id.clone(), app_state.store.clone());
 
-    let paste_type = get_paste_type(id.clone(), &paste, app_state.clone()).await?;
+    if was_burned(&id, &app_state).await? {
+        return Ok((StatusCode::GONE, ALREADY_VIEWED).into_response());
+    }
+
+    let Some(metadata) = get_paste_metadata(&id, &app_state).await? else {
+        return Ok((StatusCode::NOT_FOUND, "Paste not found").into_response());
+    };
+
+    let burn_after_read = metadata.burn_after_read;
+    if burn_after_read && !burn(&id, &metadata, &app_state).await? {
+        return Ok((StatusCode::GONE, ALREADY_VIEWED).into_response());
+    }
+
+    let response = raw_response(&paste, metadata.paste_type).await;
+
+    if burn_after_read {
+        match &response {
+            Ok(_) => delete_burned(&id, &metadata, &app_state).await?,
+            Err(_) => unburn(&id, &metadata, &app_state).await?,
+        }
+    }
+
+    response
+}
+
+/// Serves a single file of a bundle, `file` is the name it was uploaded with.
+pub async fn get_raw_member(
+    Path((id, file)): Path<(String, String)>,
+    State(app_state): State<AppState>,
+) -> Result<Response, AppError> {
+    let metadata = PasteMetadata::load(&app_state.redis_state.pool, &id).await?;
+
+    let member = metadata.and_then(|metadata| {
+        metadata
+            .members
+            .into_iter()
+            .find(|member| member.name == file)
+    });
+
+    match member {
+        Some(member) => get_raw(Path(member.id), State(app_state)).await,
+        None => Ok((StatusCode::NOT_FOUND, "File not found").into_response()),
+    }
+}
 
+async fn raw_response(paste: &Paste, paste_type: PasteType) -> Result<Response, AppError> {
     if let PasteType::Log = paste_type {
         let lines = paste.lines().await?;
 
@@ -23,8 +71,7 @@ pub async fn get_raw(
 
         Ok(lines.join("\n").into_response())
     } else {
-        let content =
-            tokio::fs::read_to_string(app_state.configuration.storage.directory.join(id)).await?;
+        let content = paste.content().await?;
 
         Ok(content.into_response())
     }
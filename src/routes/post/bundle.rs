@@ -0,0 +1,98 @@
+use crate::paste::analyzer::{PasteAnalyzer, PasteType};
+use crate::paste::metadata::BundleMember;
+use crate::routes::post::{
+    discard_upload, finish_upload, StoredFile, Upload, UploadError, UploadOptions,
+};
+use crate::AppState;
+use axum::http::HeaderMap;
+
+/// Finishes an upload of several files that share the bundle `id`.
+///
+/// The `content` field is stored under `id` already, otherwise the first log (or the first file
+/// when there's no log) is moved there, so it's the file that's shown and analyzed.
+/// All files are listed in its metadata.
+pub async fn finish_bundle(
+    app_state: &AppState,
+    id: String,
+    mut files: Vec<StoredFile>,
+    headers: &HeaderMap,
+    options: &UploadOptions,
+) -> Result<Vec<Upload>, UploadError> {
+    if !files.iter().any(|file| file.id == id) {
+        if let Err(err) = move_main_file(app_state, &id, &mut files).await {
+            discard_upload(app_state, &files, &[]).await;
+            return Err(err);
+        }
+    }
+
+    let uploads = finish_upload(app_state, files, headers, options).await?;
+
+    let members = uploads
+        .iter()
+        .map(|upload| BundleMember {
+            name: upload
+                .metadata
+                .filename
+                .clone()
+                .unwrap_or_else(|| upload.id.clone()),
+            id: upload.id.clone(),
+            paste_type: upload.metadata.paste_type,
+        })
+        .collect();
+
+    // the members are deleted together with the bundle, so only its token is handed out
+    let main = uploads
+        .iter()
+        .position(|upload| upload.id == id)
+        .unwrap_or(0);
+    let mut bundle = uploads[main].clone();
+
+    bundle.metadata.members = members;
+    if let Err(err) = bundle
+        .metadata
+        .save(&app_state.redis_state.pool, &bundle.id)
+        .await
+    {
+        discard_upload(app_state, &[], &uploads).await;
+        return Err(err.into());
+    }
+
+    Ok(vec![bundle])
+}
+
+/// Moves the first log (or the first file when there's no log) to the bundle `id`
+async fn move_main_file(
+    app_state: &AppState,
+    id: &str,
+    files: &mut [StoredFile],
+) -> Result<(), UploadError> {
+    let mut main = None;
+
+    for (index, file) in files.iter().enumerate() {
+        let content = app_state.store.get(&file.id).await?;
+        if content.is_empty() {
+            continue;
+        }
+
+        let is_log = app_state
+            .workers
+            .run(move || matches!(PasteAnalyzer::new().paste_type(&content), PasteType::Log))
+            .await
+            .map_err(UploadError::Worker)?;
+
+        if is_log || main.is_none() {
+            main = Some(index);
+        }
+
+        if is_log {
+            break;
+        }
+    }
+
+    if let Some(main) = main {
+        app_state.store.rename(&files[main].id, id).await?;
+        files[main].id = id.to_string();
+    }
+
+    Ok(())
+}
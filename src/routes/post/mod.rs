@@ -1,89 +1,434 @@
 use crate::configuration::Settings;
-use crate::paste::analyzer::PasteAnalyzer;
+use crate::paste::archive::{self, ArchiveError};
+use crate::paste::expiration::choose_expiration;
+use crate::paste::metadata::{hash, PasteMetadata};
 use crate::paste::Paste;
+use crate::routes::api::upload::Response as UploadResponse;
+use crate::routes::api::ApiError;
+use crate::routes::post::bundle::finish_bundle;
+use crate::worker::{WorkerError, RETRY_AFTER_IN_SECONDS};
 use crate::{AppError, AppState};
+use anyhow::anyhow;
 use axum::body::Bytes;
 use axum::extract::Multipart;
-use axum::http::StatusCode;
+use axum::http::header::InvalidHeaderValue;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Redirect, Response};
-use axum::BoxError;
+use axum::{BoxError, Json};
 use fred::interfaces::KeysInterface;
 use fred::types::Expiration;
 use futures::{Stream, TryStreamExt};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
+use serde::Deserialize;
 use std::io;
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, BufWriter};
+use tokio::io::AsyncReadExt;
 use tokio_util::io::StreamReader;
 use tracing::log::{error, warn};
 
+pub mod bundle;
+pub mod raw;
 pub mod upload;
 
-async fn stream_to_file<S, E>(id: String, stream: S, configuration: &Settings)
+/// A file that was saved to the store and is waiting for its metadata
+pub struct StoredFile {
+    pub id: String,
+    pub filename: Option<String>,
+    /// Name of the archive the file was extracted from
+    pub archive: Option<String>,
+}
+
+/// Saves the upload under `id`. Gzip and zip archives are extracted first, when a zip
+/// contains several logs, every one of them gets its own paste.
+pub async fn stream_to_file<S, E>(
+    id: String,
+    filename: Option<String>,
+    stream: S,
+    app_state: &AppState,
+) -> Result<Vec<StoredFile>, UploadError>
 where
-    S: Stream<Item = Result<Bytes, E>>,
+    S: Stream<Item = Result<Bytes, E>> + Send,
     E: Into<BoxError>,
 {
-    async {
-        let body_with_io_error = stream.map_err(|err| io::Error::new(io::ErrorKind::Other, err));
-        let body_reader =
-            StreamReader::new(body_with_io_error).take(configuration.paste.size_limit as u64);
-        futures::pin_mut!(body_reader);
+    let size_limit = app_state.configuration.paste.size_limit as u64;
+
+    let body_with_io_error = stream.map_err(io::Error::other);
+    let body_reader = StreamReader::new(body_with_io_error).take(size_limit);
+    futures::pin_mut!(body_reader);
 
-        let path = std::path::Path::new(&configuration.storage.directory).join(id);
-        let mut file = BufWriter::new(File::create(path).await.unwrap());
+    // the first chunk of the body can be shorter than the magic number
+    let mut header = Vec::with_capacity(archive::HEADER_LENGTH);
+    (&mut body_reader)
+        .take(archive::HEADER_LENGTH as u64)
+        .read_to_end(&mut header)
+        .await?;
+    let kind = archive::detect(&header);
+
+    let mut body_reader = header.as_slice().chain(body_reader);
 
-        if let Err(err) = tokio::io::copy(&mut body_reader, &mut file).await {
+    let Some(kind) = kind else {
+        if let Err(err) = app_state.store.put_stream(&id, &mut body_reader).await {
             error!("Streaming to file failed: {err}");
+            return Err(err.into());
+        }
+
+        return Ok(vec![StoredFile {
+            id,
+            filename,
+            archive: None,
+        }]);
+    };
+
+    let mut content = Vec::new();
+    body_reader.read_to_end(&mut content).await?;
+
+    let archive_name = filename.clone();
+    let entries = tokio::task::spawn_blocking(move || {
+        archive::extract(kind, &content, archive_name.as_deref(), size_limit)
+    })
+    .await?
+    .map_err(UploadError::Archive)?;
+
+    let mut files = Vec::with_capacity(entries.len());
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        let id = match index {
+            0 => id.clone(),
+            _ => gen_id(app_state.configuration.storage.id_length),
+        };
+
+        if let Err(err) = app_state.store.put(&id, &entry.content).await {
+            discard_upload(app_state, &files, &[]).await;
+            return Err(err.into());
+        }
+
+        files.push(StoredFile {
+            id,
+            filename: Some(entry.name),
+            archive: filename.clone(),
+        });
+    }
+
+    Ok(files)
+}
+
+/// Options the uploader can send along with the paste content
+#[derive(Deserialize, Default)]
+pub struct UploadOptions {
+    pub filename: Option<String>,
+    /// e.g. `1h`, `1d`, `7d` or `never`
+    pub expires: Option<String>,
+    #[serde(default)]
+    pub burn_after_read: bool,
+}
+
+#[derive(Clone)]
+pub struct Upload {
+    pub id: String,
+    pub metadata: PasteMetadata,
+    pub deletion_token: String,
+}
+
+pub enum UploadError {
+    Empty,
+    InvalidExpiration,
+    Archive(ArchiveError),
+    Worker(WorkerError),
+    Failed(anyhow::Error),
+}
+
+impl<E> From<E> for UploadError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(err: E) -> Self {
+        Self::Failed(err.into())
+    }
+}
+
+impl From<UploadError> for ApiError {
+    fn from(err: UploadError) -> Self {
+        match err {
+            UploadError::Empty => ApiError::Client("Paste content is empty"),
+            UploadError::InvalidExpiration => {
+                ApiError::Client("Invalid expiration, use e.g. 1h, 1d, 7d or never")
+            }
+            UploadError::Archive(ArchiveError::TooLarge) => {
+                ApiError::Client("Archive content is too large")
+            }
+            UploadError::Archive(ArchiveError::NoLogFiles) => {
+                ApiError::Client("Archive doesn't contain any log files")
+            }
+            UploadError::Archive(ArchiveError::Invalid(err)) => {
+                warn!("Couldn't extract archive: {err}");
+                ApiError::Client("Couldn't extract archive")
+            }
+            UploadError::Worker(err) => err.into(),
+            UploadError::Failed(err) => {
+                error!("Upload failed: {err}");
+                ApiError::Server("Couldn't save paste")
+            }
         }
     }
-    .await
 }
 
-#[allow(dependency_on_unit_never_type_fallback)]
-async fn upload_file(app_state: AppState, mut multipart: Multipart) -> Result<Response, AppError> {
+/// Generates and saves metadata for every stored file of the upload.
+/// The files are deleted again if the upload is rejected or fails.
+pub async fn finish_upload(
+    app_state: &AppState,
+    files: Vec<StoredFile>,
+    headers: &HeaderMap,
+    options: &UploadOptions,
+) -> Result<Vec<Upload>, UploadError> {
+    let mut uploads = Vec::with_capacity(files.len());
+
+    match save_metadata(app_state, &files, headers, options, &mut uploads).await {
+        Ok(()) if uploads.is_empty() => Err(UploadError::Empty),
+        Ok(()) => Ok(uploads),
+        Err(err) => {
+            discard_upload(app_state, &files, &uploads).await;
+            Err(err)
+        }
+    }
+}
+
+/// Every file whose metadata was saved is added to `uploads`, empty files are deleted.
+async fn save_metadata(
+    app_state: &AppState,
+    files: &[StoredFile],
+    headers: &HeaderMap,
+    options: &UploadOptions,
+    uploads: &mut Vec<Upload>,
+) -> Result<(), UploadError> {
+    let expires_in = choose_expiration(options.expires.as_deref(), &app_state.configuration)
+        .map_err(|err| {
+            warn!("{err}");
+            UploadError::InvalidExpiration
+        })?;
+
+    for file in files {
+        let paste = Paste::new(file.id.clone(), app_state.store.clone());
+
+        let content = paste.content().await?;
+
+        if content.is_empty() {
+            paste.delete().await?;
+            continue;
+        }
+
+        let mut metadata = PasteMetadata::generate(
+            &paste,
+            content.into_bytes(),
+            expires_in,
+            &app_state.configuration,
+            &app_state.workers,
+        )
+        .await
+        .map_err(UploadError::Worker)?;
+        metadata.filename = file.filename.clone();
+        metadata.archive = file.archive.clone();
+        metadata.burn_after_read = options.burn_after_read;
+
+        let deletion_token = gen_deletion_token();
+        metadata.deletion_token = Some(hash(deletion_token.as_bytes()));
+        metadata.uploader = uploader_token(headers).map(|token| hash(token.as_bytes()));
+
+        let expires_at = metadata.expires_at;
+        metadata.save(&app_state.redis_state.pool, &file.id).await?;
+        uploads.push(Upload {
+            id: file.id.clone(),
+            metadata,
+            deletion_token,
+        });
+
+        paste.expire(expires_at).await?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the stored files of a rejected upload, and the metadata of the ones that got it
+/// already. `uploads` that aren't in `files` are deleted as well.
+async fn discard_upload(app_state: &AppState, files: &[StoredFile], uploads: &[Upload]) {
+    for upload in uploads {
+        let deleted: Result<(), _> = app_state.redis_state.pool.del(&upload.id).await;
+        if let Err(err) = deleted {
+            warn!(
+                "Couldn't delete metadata of rejected upload '{}': {err}",
+                upload.id
+            );
+        }
+    }
+
+    let mut ids: Vec<&String> = files.iter().map(|file| &file.id).collect();
+    for upload in uploads {
+        if !ids.contains(&&upload.id) {
+            ids.push(&upload.id);
+        }
+    }
+
+    for id in ids {
+        match app_state.store.delete(id).await {
+            // empty files are deleted while the upload is finished
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                warn!("Couldn't delete rejected upload '{id}': {err}");
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Form uploads are redirected back to the start page when they're rejected
+fn upload_error_response(err: UploadError) -> Result<Response, AppError> {
+    match err {
+        UploadError::Failed(err) => Err(AppError(err)),
+        UploadError::Worker(WorkerError::Busy) => Ok((
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, RETRY_AFTER_IN_SECONDS.to_string())],
+            Redirect::to("/"),
+        )
+            .into_response()),
+        UploadError::Worker(err) => Err(AppError(anyhow!("Upload failed: {err}"))),
+        _ => Ok((StatusCode::BAD_REQUEST, Redirect::to("/")).into_response()),
+    }
+}
+
+async fn upload_file(
+    app_state: AppState,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Response, AppError> {
     let id = gen_id(app_state.configuration.storage.id_length);
+    let mut options = UploadOptions::default();
+    let mut files = Vec::new();
+    let mut members = 0;
 
     while let Ok(Some(field)) = multipart.next_field().await {
-        if let Some("content") = field.name() {
-            stream_to_file(id.clone(), field, &app_state.configuration).await;
+        let name = field.name().map(str::to_string);
+
+        let stored = match name.as_deref() {
+            Some("content") => {
+                let filename = field.file_name().map(str::to_string);
+                stream_to_file(id.clone(), filename, field, &app_state).await
+            }
+            Some("expires") => match field.text().await {
+                Ok(expires) => {
+                    options.expires = Some(expires);
+                    continue;
+                }
+                Err(err) => Err(err.into()),
+            },
+            Some("burn_after_read") => match field.text().await {
+                Ok(burn_after_read) => {
+                    options.burn_after_read =
+                        matches!(burn_after_read.as_str(), "true" | "1" | "on");
+                    continue;
+                }
+                Err(err) => Err(err.into()),
+            },
+            Some(name) => {
+                // unknown fields are ignored
+                let Some(member) = member_name(name) else {
+                    continue;
+                };
+                let member = Some(member.to_string());
+
+                members += 1;
+                stream_to_file(format!("{id}-{members}"), member, field, &app_state).await
+            }
+            None => continue,
+        };
+
+        match stored {
+            Ok(stored) => files.extend(stored),
+            Err(err) => {
+                discard_upload(&app_state, &files, &[]).await;
+                return upload_error_response(err);
+            }
         }
     }
 
-    let paste = Paste::new(id.clone(), app_state.configuration.clone());
+    let uploads = if members > 0 {
+        finish_bundle(&app_state, id, files, &headers, &options).await
+    } else {
+        finish_upload(&app_state, files, &headers, &options).await
+    };
+
+    let uploads = match uploads {
+        Ok(uploads) => uploads,
+        Err(err) => return upload_error_response(err),
+    };
+
+    let mut response = if accepts_json(&headers) {
+        Json(UploadResponse::new(
+            uploads.clone(),
+            &app_state.configuration,
+        ))
+        .into_response()
+    } else {
+        Redirect::to(&format!("/{}", uploads[0].id)).into_response()
+    };
+    insert_upload_headers(&mut response, &uploads)?;
+
+    Ok(response)
+}
+
+/// Files of a bundle are sent in `files[<name>]` fields, e.g. `files[server.properties]`
+fn member_name(field_name: &str) -> Option<&str> {
+    field_name
+        .strip_prefix("files[")?
+        .strip_suffix(']')
+        .filter(|name| !name.is_empty())
+}
+
+/// Upload responses that aren't JSON carry the expiration and deletion token of the (first)
+/// paste in headers. Other pastes extracted from the same zip are listed in `x-extracted-ids`.
+pub fn insert_upload_headers(
+    response: &mut Response,
+    uploads: &[Upload],
+) -> Result<(), InvalidHeaderValue> {
+    let headers = response.headers_mut();
 
-    let content = tokio::fs::read_to_string(paste.file_path()).await?;
+    let Some((upload, extracted)) = uploads.split_first() else {
+        return Ok(());
+    };
 
-    if content.is_empty() {
-        tokio::fs::remove_file(paste.file_path()).await?;
-        return Ok((StatusCode::BAD_REQUEST, Redirect::to("/")).into_response());
+    headers.insert(
+        "x-expires-at",
+        upload
+            .metadata
+            .expires_at
+            .map_or(HeaderValue::from_static("never"), HeaderValue::from),
+    );
+    headers.insert(
+        "x-deletion-token",
+        HeaderValue::from_str(&upload.deletion_token)?,
+    );
+
+    if !extracted.is_empty() {
+        let ids: Vec<&str> = extracted.iter().map(|upload| upload.id.as_str()).collect();
+        headers.insert("x-extracted-ids", HeaderValue::from_str(&ids.join(","))?);
     }
 
-    let paste_analyzer = PasteAnalyzer::new();
-    let paste_type = paste_analyzer.paste_type(content.as_bytes());
-
-    app_state
-        .redis_state
-        .pool
-        .set(
-            id.clone(),
-            paste_type,
-            get_expiration(&app_state.configuration),
-            None,
-            false,
-        )
-        .await?;
+    Ok(())
+}
 
-    Ok(Redirect::to(&format!("/{}", id)).into_response())
+pub fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
 }
 
 pub fn get_expiration(configuration: &Settings) -> Option<Expiration> {
+    expiration_in_seconds(configuration).map(|seconds| Expiration::EX(seconds as i64))
+}
+
+pub fn expiration_in_seconds(configuration: &Settings) -> Option<u64> {
     let expiration_in_hours = configuration.storage.expiration_in_hours;
 
     if expiration_in_hours > 0.0 {
-        let expiration_in_seconds = (expiration_in_hours * 3600.0) as i64;
-        Some(Expiration::EX(expiration_in_seconds))
+        Some((expiration_in_hours * 3600.0) as u64)
     } else {
         if expiration_in_hours != 0.0 {
             warn!("Expiration is set to '{}' but should be set to positive number or 0 (disabled).\nExpiration is disabled.", expiration_in_hours);
@@ -92,6 +437,16 @@ pub fn get_expiration(configuration: &Settings) -> Option<Expiration> {
     }
 }
 
+pub fn uploader_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("x-api-key")
+        .and_then(|token| token.to_str().ok())
+}
+
+pub fn gen_deletion_token() -> String {
+    gen_id(32)
+}
+
 pub fn gen_id(length: u16) -> String {
     rand::thread_rng()
         .sample_iter(&Alphanumeric)
@@ -0,0 +1,62 @@
+use crate::routes::api::upload::Response as UploadResponse;
+use crate::routes::api::ApiError;
+use crate::routes::post::{
+    accepts_json, finish_upload, gen_id, insert_upload_headers, stream_to_file, UploadOptions,
+};
+use crate::AppState;
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+/// Takes the paste content as the raw request body, e.g. `curl --data-binary @latest.log`.
+/// Upload options are passed in the query string.
+///
+/// Responds with the paste URL as plain text, or with JSON if the client accepts it.
+/// A zip with several logs creates several pastes, their URLs are returned one per line.
+pub async fn upload_raw(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    Query(options): Query<UploadOptions>,
+    body: Body,
+) -> Result<Response, ApiError> {
+    let id = gen_id(app_state.configuration.storage.id_length);
+
+    let files = stream_to_file(
+        id,
+        options.filename.clone(),
+        body.into_data_stream(),
+        &app_state,
+    )
+    .await?;
+
+    let uploads = finish_upload(&app_state, files, &headers, &options).await?;
+
+    let mut response = if accepts_json(&headers) {
+        Json(UploadResponse::new(
+            uploads.clone(),
+            &app_state.configuration,
+        ))
+        .into_response()
+    } else {
+        let public_url = app_state
+            .configuration
+            .application
+            .public_url
+            .trim_end_matches('/');
+
+        uploads
+            .iter()
+            .map(|upload| format!("{public_url}/{}\n", upload.id))
+            .collect::<String>()
+            .into_response()
+    };
+
+    insert_upload_headers(&mut response, &uploads)
+        .map_err(|_| ApiError::Server("Couldn't save paste"))?;
+
+    Ok(response)
+}
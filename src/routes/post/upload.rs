@@ -2,12 +2,14 @@ use crate::routes::post::upload_file;
 use crate::{AppError, AppState};
 use axum::{
     extract::{Multipart, State},
+    http::HeaderMap,
     response::Response,
 };
 
 pub async fn post_upload(
     State(app_state): State<AppState>,
+    headers: HeaderMap,
     multipart: Multipart,
 ) -> Result<Response, AppError> {
-    upload_file(app_state, multipart).await
+    upload_file(app_state, headers, multipart).await
 }
@@ -0,0 +1,255 @@
+use crate::paste::metadata::{hash, unix_timestamp};
+use mclog::analyzer::dynamic::{ScriptPlatform, SCRIPTS_DIRECTORY};
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+const EXTENSION: &str = ".rhai";
+
+/// Appended to the file name of disabled scripts, so they aren't loaded
+const DISABLED_EXTENSION: &str = ".disabled";
+
+/// A script file in [`SCRIPTS_DIRECTORY`], named without its extension
+pub struct ScriptFile {
+    pub platform: ScriptPlatform,
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct ScriptEntry {
+    pub platform: String,
+    pub name: String,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Enable,
+    Disable,
+    Delete,
+}
+
+/// Line of the audit log, which is JSON Lines
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditEntry<'a> {
+    timestamp: u64,
+    action: AuditAction,
+    platform: &'a str,
+    name: &'a str,
+    /// SHA-256 of the script before and after the change
+    previous_hash: Option<String>,
+    hash: Option<String>,
+}
+
+/// Platform whose [`ScriptPlatform::directory`] is `directory`
+pub fn platform(directory: &str) -> Option<ScriptPlatform> {
+    ScriptPlatform::iter().find(|platform| platform.directory() == directory)
+}
+
+impl ScriptFile {
+    /// `None` when the name could escape the platform directory or isn't a plain file name
+    pub fn new(platform: ScriptPlatform, name: &str) -> Option<Self> {
+        let name = name.strip_suffix(EXTENSION).unwrap_or(name);
+
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|char| char.is_ascii_alphanumeric() || char == '_' || char == '-');
+
+        valid.then(|| Self {
+            platform,
+            name: name.to_string(),
+        })
+    }
+
+    fn directory(&self) -> PathBuf {
+        SCRIPTS_DIRECTORY.join(self.platform.directory())
+    }
+
+    fn enabled_path(&self) -> PathBuf {
+        self.directory().join(format!("{}{EXTENSION}", self.name))
+    }
+
+    fn disabled_path(&self) -> PathBuf {
+        self.directory()
+            .join(format!("{}{EXTENSION}{DISABLED_EXTENSION}", self.name))
+    }
+
+    /// Path of the script and whether it's enabled, `None` when it doesn't exist
+    async fn find(&self) -> io::Result<Option<(PathBuf, bool)>> {
+        for (path, enabled) in [(self.enabled_path(), true), (self.disabled_path(), false)] {
+            if tokio::fs::try_exists(&path).await? {
+                return Ok(Some((path, enabled)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub async fn read(&self) -> io::Result<Option<(String, bool)>> {
+        let Some((path, enabled)) = self.find().await? else {
+            return Ok(None);
+        };
+
+        Ok(Some((tokio::fs::read_to_string(path).await?, enabled)))
+    }
+
+    /// Creates or replaces the script, a disabled script stays disabled.
+    pub async fn write(&self, source: &str, audit_log: &Path) -> io::Result<AuditAction> {
+        let existing = self.find().await?;
+
+        let (path, previous_hash) = match &existing {
+            Some((path, _)) => (path.clone(), Some(hash(&tokio::fs::read(path).await?))),
+            None => (self.enabled_path(), None),
+        };
+
+        tokio::fs::create_dir_all(self.directory()).await?;
+        write_atomically(&path, source.as_bytes()).await?;
+
+        let action = match existing {
+            Some(_) => AuditAction::Update,
+            None => AuditAction::Create,
+        };
+        self.audit(
+            audit_log,
+            action,
+            previous_hash,
+            Some(hash(source.as_bytes())),
+        )
+        .await?;
+
+        Ok(action)
+    }
+
+    /// Returns `false` when the script doesn't exist.
+    pub async fn set_enabled(&self, enabled: bool, audit_log: &Path) -> io::Result<bool> {
+        let Some((path, was_enabled)) = self.find().await? else {
+            return Ok(false);
+        };
+
+        if enabled == was_enabled {
+            return Ok(true);
+        }
+
+        let target = match enabled {
+            true => self.enabled_path(),
+            false => self.disabled_path(),
+        };
+        tokio::fs::rename(&path, &target).await?;
+
+        let action = match enabled {
+            true => AuditAction::Enable,
+            false => AuditAction::Disable,
+        };
+        let hash = Some(hash(&tokio::fs::read(&target).await?));
+        self.audit(audit_log, action, hash.clone(), hash).await?;
+
+        Ok(true)
+    }
+
+    /// Returns `false` when the script doesn't exist.
+    pub async fn delete(&self, audit_log: &Path) -> io::Result<bool> {
+        let Some((path, _)) = self.find().await? else {
+            return Ok(false);
+        };
+
+        let previous_hash = Some(hash(&tokio::fs::read(&path).await?));
+        tokio::fs::remove_file(&path).await?;
+
+        self.audit(audit_log, AuditAction::Delete, previous_hash, None)
+            .await?;
+
+        Ok(true)
+    }
+
+    async fn audit(
+        &self,
+        audit_log: &Path,
+        action: AuditAction,
+        previous_hash: Option<String>,
+        hash: Option<String>,
+    ) -> io::Result<()> {
+        let entry = AuditEntry {
+            timestamp: unix_timestamp(),
+            action,
+            platform: self.platform.directory(),
+            name: &self.name,
+            previous_hash,
+            hash,
+        };
+
+        let mut line = serde_json::to_string(&entry).expect("AuditEntry is always serializable");
+        line.push('\n');
+
+        if let Some(parent) = audit_log.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(audit_log)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await
+    }
+}
+
+/// Scripts of the platform, disabled ones included, sorted by name
+pub async fn list(platform: ScriptPlatform) -> io::Result<Vec<ScriptEntry>> {
+    let directory = SCRIPTS_DIRECTORY.join(platform.directory());
+
+    let mut entries = match tokio::fs::read_dir(&directory).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut scripts = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        let (name, enabled) = match file_name.strip_suffix(DISABLED_EXTENSION) {
+            Some(name) => (name, false),
+            None => (file_name.as_str(), true),
+        };
+
+        if let Some(name) = name.strip_suffix(EXTENSION) {
+            scripts.push(ScriptEntry {
+                platform: platform.directory().to_string(),
+                name: name.to_string(),
+                enabled,
+            });
+        }
+    }
+    scripts.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(scripts)
+}
+
+/// Writes to a temporary file next to `path` and renames it, so the script is never
+/// loaded half written.
+async fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::other("path has no file name"))?;
+    let temporary = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+
+    let mut file = tokio::fs::File::create(&temporary).await?;
+    file.write_all(content).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    if let Err(err) = tokio::fs::rename(&temporary, path).await {
+        let _ = tokio::fs::remove_file(&temporary).await;
+        return Err(err);
+    }
+
+    Ok(())
+}
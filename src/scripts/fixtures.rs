@@ -0,0 +1,159 @@
+use crate::configuration::AnalyzerLinesLimits;
+use crate::scripts::{Match, Scripts};
+use anyhow::Context;
+use mclog::analyzer::Analyzer;
+use mclog::log::Log;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+/// Every directory in it with a `latest.log` is a fixture, e.g. `bukkit/ambiguous_plugin_name/`
+pub const FIXTURES_DIRECTORY: &str = "tests/fixtures";
+
+const FIXTURE_LOG: &str = "latest.log";
+
+/// Detections the scripts should return for the log of the fixture
+const FIXTURE_EXPECTED: &str = "expected.toml";
+
+#[derive(Deserialize)]
+struct Expected {
+    #[serde(default)]
+    detections: Vec<ExpectedDetection>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ExpectedDetection {
+    identifier: String,
+    #[serde(default)]
+    captures: BTreeMap<String, String>,
+}
+
+impl From<Match> for ExpectedDetection {
+    fn from(matched: Match) -> Self {
+        Self {
+            identifier: matched.identifier,
+            captures: matched.captures,
+        }
+    }
+}
+
+impl fmt::Display for ExpectedDetection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.identifier)?;
+
+        if !self.captures.is_empty() {
+            let captures: Vec<String> = self
+                .captures
+                .iter()
+                .map(|(name, value)| format!("{name} = {value:?}"))
+                .collect();
+            write!(f, " {{ {} }}", captures.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the scripts against every fixture and prints the differences to what's expected.
+/// Returns whether all fixtures passed.
+pub async fn test_scripts(
+    scripts: &Scripts,
+    limits: &AnalyzerLinesLimits,
+    directory: &Path,
+) -> anyhow::Result<bool> {
+    let fixtures = fixtures(directory)?;
+    let mut failed = 0;
+
+    for fixture in &fixtures {
+        let name = fixture.strip_prefix(directory).unwrap_or(fixture).display();
+
+        match test_fixture(scripts, limits, fixture).await {
+            Ok((missing, unexpected)) if missing.is_empty() && unexpected.is_empty() => {
+                println!("PASS {name}");
+            }
+            Ok((missing, unexpected)) => {
+                failed += 1;
+                println!("FAIL {name}");
+                for detection in missing {
+                    println!("  - {detection}");
+                }
+                for detection in unexpected {
+                    println!("  + {detection}");
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                println!("FAIL {name}\n  {err:#}");
+            }
+        }
+    }
+
+    println!("\n{} passed, {failed} failed", fixtures.len() - failed);
+
+    Ok(failed == 0)
+}
+
+/// Detections that are expected but weren't returned, and the other way around
+async fn test_fixture(
+    scripts: &Scripts,
+    limits: &AnalyzerLinesLimits,
+    fixture: &Path,
+) -> anyhow::Result<(Vec<ExpectedDetection>, Vec<ExpectedDetection>)> {
+    let expected = std::fs::read_to_string(fixture.join(FIXTURE_EXPECTED))
+        .with_context(|| format!("couldn't read {FIXTURE_EXPECTED}"))?;
+    let expected: Expected =
+        toml::from_str(&expected).with_context(|| format!("invalid {FIXTURE_EXPECTED}"))?;
+
+    let file = tokio::fs::File::open(fixture.join(FIXTURE_LOG))
+        .await
+        .with_context(|| format!("couldn't read {FIXTURE_LOG}"))?;
+    let lines = Log::new(BufReader::new(file).lines())
+        .first_n_lines_hideips(limits.max())
+        .await;
+
+    let details = Analyzer::new(&lines).build(limits.plugins, limits.ports);
+
+    let mut missing = expected.detections;
+    missing.sort();
+
+    let mut unexpected = Vec::new();
+    for detection in scripts.matches(details) {
+        let detection = ExpectedDetection::from(detection);
+
+        match missing.iter().position(|expected| *expected == detection) {
+            Some(index) => {
+                missing.remove(index);
+            }
+            None => unexpected.push(detection),
+        }
+    }
+    unexpected.sort();
+
+    Ok((missing, unexpected))
+}
+
+fn fixtures(directory: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut fixtures = Vec::new();
+
+    let entries = std::fs::read_dir(directory)
+        .with_context(|| format!("couldn't read {}", directory.display()))?;
+
+    for entry in entries {
+        let path = entry?.path();
+
+        if !path.is_dir() {
+            continue;
+        }
+
+        if path.join(FIXTURE_LOG).exists() {
+            fixtures.push(path);
+        } else {
+            fixtures.extend(self::fixtures(&path)?);
+        }
+    }
+    fixtures.sort();
+
+    Ok(fixtures)
+}
@@ -0,0 +1,63 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters of a single script, kept across reloads of the script.
+#[derive(Default)]
+pub struct ScriptMetrics {
+    runs: AtomicU64,
+    errors: AtomicU64,
+    limits_exceeded: AtomicU64,
+    microseconds: AtomicU64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptMetricsSnapshot {
+    pub runs: u64,
+    /// Runs that failed with a runtime error
+    pub errors: u64,
+    /// Runs stopped by the limits in `[analyzer.scripts]`
+    pub limits_exceeded: u64,
+    pub average_microseconds: u64,
+}
+
+impl ScriptMetrics {
+    pub fn record(&self, elapsed: Duration) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        self.microseconds
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_limit_exceeded(&self) {
+        self.limits_exceeded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ScriptMetricsSnapshot {
+        let runs = self.runs.load(Ordering::Relaxed);
+
+        ScriptMetricsSnapshot {
+            runs,
+            errors: self.errors.load(Ordering::Relaxed),
+            limits_exceeded: self.limits_exceeded.load(Ordering::Relaxed),
+            average_microseconds: self
+                .microseconds
+                .load(Ordering::Relaxed)
+                .checked_div(runs)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ScriptMetricsReport {
+    pub file: String,
+    /// [`ScriptPlatform::directory`](mclog::analyzer::dynamic::ScriptPlatform::directory)
+    pub platform: String,
+    #[serde(flatten)]
+    pub metrics: ScriptMetricsSnapshot,
+}
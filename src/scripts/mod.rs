@@ -0,0 +1,463 @@
+use crate::configuration::AnalyzerScripts;
+use crate::scripts::metrics::{ScriptMetrics, ScriptMetricsReport};
+use crate::scripts::sandbox::Limit;
+use anyhow::{anyhow, Context};
+use mclog::analyzer::dynamic::chunks::Captures;
+use mclog::analyzer::dynamic::{Detection, ScriptPlatform, SCRIPTS_DIRECTORY};
+use mclog::analyzer::{DynamicAnalyzerDetails, Platform};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+pub mod files;
+pub mod fixtures;
+pub mod metrics;
+pub mod sandbox;
+pub mod trace;
+pub mod validation;
+
+/// Separates the script from its detections, which are written in TOML
+const DETECTIONS_SEPARATOR: &str = "///";
+
+/// Changes to the scripts directory usually come in bursts (editors, `git pull`),
+/// they're reloaded once it's quiet for this long.
+const RELOAD_DELAY: Duration = Duration::from_millis(500);
+
+/// A detection returned by a script
+#[derive(Serialize, Clone)]
+pub struct Match {
+    pub identifier: String,
+    pub captures: BTreeMap<String, String>,
+    pub detection: Detection,
+}
+
+#[derive(Clone)]
+pub struct Script {
+    pub file: PathBuf,
+    pub ast: AST,
+    pub detection: HashMap<String, Detection>,
+    /// SHA-256 of the script file
+    hash: String,
+    pub metrics: Arc<ScriptMetrics>,
+}
+
+/// All scripts, compiled once with the engine of
+/// [`DynamicAnalyzer`](mclog::analyzer::dynamic::DynamicAnalyzer).
+pub struct Scripts {
+    /// Shared by every reload, see [`sandbox::engine`]
+    engine: Arc<Engine>,
+    limits: AnalyzerScripts,
+    /// Scripts by [`ScriptPlatform::directory`]
+    scripts: HashMap<String, Vec<Script>>,
+    version: String,
+}
+
+impl Scripts {
+    /// Compiles every script in [`SCRIPTS_DIRECTORY`]. A script that doesn't compile is logged
+    /// and skipped, or replaced with its version from `previous` if there is one.
+    /// The engine of `previous` is reused, its limits are the same.
+    pub fn load(limits: &AnalyzerScripts, previous: Option<&Scripts>) -> Self {
+        let engine = match previous {
+            Some(previous) => previous.engine.clone(),
+            None => Arc::new(sandbox::engine(limits)),
+        };
+        let mut scripts = HashMap::new();
+
+        for platform in ScriptPlatform::iter() {
+            let directory = platform.directory().to_string();
+            let mut platform_scripts = Vec::new();
+
+            for file in script_files(&SCRIPTS_DIRECTORY.join(&directory)) {
+                let previous = previous.and_then(|previous| previous.script(&directory, &file));
+
+                match compile(&engine, &file) {
+                    Ok(mut script) => {
+                        if let Some(previous) = previous {
+                            script.metrics = previous.metrics.clone();
+                        }
+                        platform_scripts.push(script)
+                    }
+                    Err(err) => {
+                        error!("Couldn't load script {}: {err:#}", file.display());
+
+                        if let Some(previous) = previous {
+                            warn!("Keeping the previous version of {}", file.display());
+                            platform_scripts.push(previous.clone());
+                        }
+                    }
+                }
+            }
+
+            scripts.insert(directory, platform_scripts);
+        }
+
+        let mut hasher = Sha256::new();
+        for platform in ScriptPlatform::iter() {
+            for script in &scripts[platform.directory()] {
+                hasher.update(script.file.to_string_lossy().as_bytes());
+                hasher.update(script.hash.as_bytes());
+            }
+        }
+
+        Self {
+            engine,
+            limits: limits.clone(),
+            scripts,
+            version: hex::encode(hasher.finalize()),
+        }
+    }
+
+    pub fn metrics(&self) -> Vec<ScriptMetricsReport> {
+        let mut reports = Vec::new();
+
+        for platform in ScriptPlatform::iter() {
+            for script in self.platform(platform) {
+                reports.push(ScriptMetricsReport {
+                    file: script.file.to_string_lossy().to_string(),
+                    platform: platform.directory().to_string(),
+                    metrics: script.metrics.snapshot(),
+                });
+            }
+        }
+
+        reports
+    }
+
+    /// Changes whenever a script is added, changed or removed.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn len(&self) -> usize {
+        self.scripts.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn platform(&self, platform: ScriptPlatform) -> &[Script] {
+        self.scripts
+            .get(platform.directory())
+            .map_or(&[], Vec::as_slice)
+    }
+
+    fn script(&self, directory: &str, file: &Path) -> Option<&Script> {
+        self.scripts
+            .get(directory)?
+            .iter()
+            .find(|script| script.file == file)
+    }
+
+    /// Compiles a script that isn't in the scripts directory with the same engine and limits.
+    pub fn compile(&self, file: &Path, source: &str) -> anyhow::Result<Script> {
+        parse(&self.engine, file, source)
+    }
+
+    /// Runs a single script, whether or not it applies to the platform of the log.
+    /// Unlike [`Scripts::matches`], a runtime error is returned instead of logged.
+    pub fn dry_run(
+        &self,
+        script: &Script,
+        dad: DynamicAnalyzerDetails,
+    ) -> Result<Vec<Match>, Box<EvalAltResult>> {
+        let mut scope = Scope::new();
+        scope.push_constant("dad", dad);
+
+        let result = self.eval(script, &mut scope)?;
+
+        Ok(script.matches(result))
+    }
+
+    fn eval(&self, script: &Script, scope: &mut Scope) -> Result<Dynamic, Box<EvalAltResult>> {
+        let started = Instant::now();
+        let result = sandbox::with_timeout(&self.limits, || {
+            self.engine
+                .eval_ast_with_scope::<Dynamic>(scope, &script.ast)
+        });
+        script.metrics.record(started.elapsed());
+
+        result
+    }
+
+    /// Evaluates the script within the limits, a script that fails or exceeds them returns unit.
+    fn run(&self, script: &Script, scope: &mut Scope) -> Dynamic {
+        match self.eval(script, scope) {
+            Ok(return_code) => return_code,
+            Err(err) => {
+                script.report(&err);
+                Dynamic::UNIT
+            }
+        }
+    }
+
+    /// Scripts that apply to the platform of the log, with the platform they were selected for
+    fn applicable(&self, dad: &DynamicAnalyzerDetails) -> Vec<(ScriptPlatform, &Script)> {
+        let mut platforms = vec![ScriptPlatform::Global];
+
+        match dad.platform {
+            Platform::Vanilla => {}
+            Platform::CraftBukkit
+            | Platform::Spigot
+            | Platform::Paper
+            | Platform::Pufferfish
+            | Platform::Purpur => platforms.push(ScriptPlatform::Bukkit),
+            Platform::Fabric => platforms.push(ScriptPlatform::Fabric),
+            Platform::Forge => platforms.push(ScriptPlatform::Forge),
+            Platform::BungeeCord | Platform::Waterfall => {
+                platforms.push(ScriptPlatform::BungeeCord)
+            }
+            Platform::Velocity => platforms.push(ScriptPlatform::Velocity),
+        }
+
+        if !dad.is_proxy {
+            platforms.push(ScriptPlatform::NoProxy);
+        }
+
+        platforms
+            .into_iter()
+            .flat_map(|platform| {
+                self.platform(platform)
+                    .iter()
+                    .map(move |script| (platform, script))
+            })
+            .collect()
+    }
+
+    /// Runs the scripts that apply to the platform of the log, private detections included.
+    pub fn matches(&self, dad: DynamicAnalyzerDetails) -> Vec<Match> {
+        let scripts = self.applicable(&dad);
+
+        let mut scope = Scope::new();
+        scope.push_constant("dad", dad);
+
+        let mut matches = Vec::new();
+
+        for (_, script) in scripts {
+            let result = self.run(script, &mut scope);
+            matches.extend(script.matches(result));
+        }
+
+        matches
+    }
+
+    /// Detections of the log sorted by level, private detections included.
+    pub fn detections(&self, dad: DynamicAnalyzerDetails) -> Vec<Detection> {
+        let mut detections: Vec<Detection> = self
+            .matches(dad)
+            .into_iter()
+            .map(|matched| matched.detection)
+            .collect();
+        detections.sort_by_key(|item| item.level);
+
+        detections
+    }
+}
+
+impl Script {
+    /// Logs the error of a run and counts it in the metrics.
+    fn report(&self, err: &EvalAltResult) {
+        match Limit::of(err) {
+            Some(limit) => {
+                self.metrics.record_limit_exceeded();
+                warn!(
+                    "Script {} exceeded the {limit}, skipping..",
+                    self.file.display()
+                );
+            }
+            None => {
+                self.metrics.record_error();
+                error!("{} in file {}", err, self.file.display());
+            }
+        }
+    }
+
+    /// Detections for what the script returned, either an identifier or a list of captures.
+    fn matches(&self, result: Dynamic) -> Vec<Match> {
+        self.resolve(&result)
+            .into_iter()
+            .filter_map(|matched| {
+                matched
+                    .inspect_err(|err| error!("{err} from file {}", self.file.display()))
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Like [`Script::matches`], with an error for every identifier without a detection
+    /// and for results that are neither an identifier nor captures.
+    fn resolve(&self, result: &Dynamic) -> Vec<Result<Match, String>> {
+        if result.is_unit() {
+            return Vec::new();
+        }
+
+        if result.is_string() {
+            let identifier = result.to_string();
+
+            return vec![self.matched(identifier, BTreeMap::new())];
+        }
+
+        if let Some(results) = result.read_lock::<Vec<Captures>>() {
+            return results
+                .iter()
+                .map(|result| {
+                    let captures = result.captures.clone().into_iter().collect();
+                    self.matched(result.identifier.clone(), captures)
+                })
+                .collect();
+        }
+
+        vec![Err(format!("Unexpected result type for {result:?}"))]
+    }
+
+    /// The detection of `identifier` with the captures filled in its header and solutions
+    fn matched(
+        &self,
+        identifier: String,
+        captures: BTreeMap<String, String>,
+    ) -> Result<Match, String> {
+        let Some(detection) = self.detection.get(&identifier) else {
+            return Err(format!(
+                "Error while obtaining detection info for {identifier}"
+            ));
+        };
+
+        let mut detection = detection.clone();
+
+        for (name, value) in &captures {
+            let placeholder = format!("{{{name}}}");
+
+            detection.header = detection.header.replace(&placeholder, value);
+            for solution in &mut detection.solutions {
+                *solution = solution.replace(&placeholder, value);
+            }
+        }
+
+        Ok(Match {
+            identifier,
+            captures,
+            detection,
+        })
+    }
+}
+
+fn script_files(directory: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "rhai")
+        })
+        .collect();
+    files.sort();
+
+    files
+}
+
+fn compile(engine: &Engine, file: &Path) -> anyhow::Result<Script> {
+    let source = std::fs::read_to_string(file).context("couldn't read the file")?;
+
+    parse(engine, file, &source)
+}
+
+/// Compiles the script and parses the detections after the [`DETECTIONS_SEPARATOR`].
+fn parse(engine: &Engine, file: &Path, source: &str) -> anyhow::Result<Script> {
+    let mut lines = source.lines();
+    let code: Vec<&str> = lines
+        .by_ref()
+        .take_while(|line| line.trim() != DETECTIONS_SEPARATOR)
+        .collect();
+    let detections: Vec<&str> = lines.collect();
+
+    let ast = engine
+        .compile(code.join("\n"))
+        .map_err(|err| anyhow!("invalid script: {err}"))?;
+    let detection = toml::from_str(&detections.join("\n")).context("invalid detections")?;
+
+    Ok(Script {
+        file: file.to_path_buf(),
+        ast,
+        detection,
+        hash: hex::encode(Sha256::digest(source.as_bytes())),
+        metrics: Arc::default(),
+    })
+}
+
+/// Shared handle to the current [`Scripts`]. A reload compiles the new scripts first
+/// and then swaps them in at once, running analyses keep the scripts they started with.
+#[derive(Clone)]
+pub struct ScriptRegistry {
+    current: Arc<RwLock<Arc<Scripts>>>,
+    limits: AnalyzerScripts,
+}
+
+impl ScriptRegistry {
+    pub fn load(limits: &AnalyzerScripts) -> Self {
+        let scripts = Scripts::load(limits, None);
+        info!("Loaded {} scripts", scripts.len());
+
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(scripts))),
+            limits: limits.clone(),
+        }
+    }
+
+    pub fn current(&self) -> Arc<Scripts> {
+        self.current
+            .read()
+            .expect("Scripts lock is never poisoned")
+            .clone()
+    }
+
+    pub fn reload(&self) {
+        let scripts = Scripts::load(&self.limits, Some(&self.current()));
+        info!("Reloaded {} scripts", scripts.len());
+
+        *self
+            .current
+            .write()
+            .expect("Scripts lock is never poisoned") = Arc::new(scripts);
+    }
+
+    /// Reloads the scripts whenever something in [`SCRIPTS_DIRECTORY`] changes.
+    /// Watching stops when the returned watcher is dropped.
+    pub fn watch(&self) -> notify::Result<RecommendedWatcher> {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(event) if !event.kind.is_access() => {
+                    let _ = sender.send(());
+                }
+                Ok(_) => {}
+                Err(err) => error!("Watching scripts failed: {err}"),
+            })?;
+        watcher.watch(&SCRIPTS_DIRECTORY, RecursiveMode::Recursive)?;
+
+        let registry = self.clone();
+        tokio::spawn(async move {
+            while receiver.recv().await.is_some() {
+                tokio::time::sleep(RELOAD_DELAY).await;
+                while receiver.try_recv().is_ok() {}
+
+                let registry = registry.clone();
+                if let Err(err) = tokio::task::spawn_blocking(move || registry.reload()).await {
+                    error!("Reloading scripts failed: {err}");
+                }
+            }
+        });
+
+        Ok(watcher)
+    }
+}
@@ -0,0 +1,92 @@
+use crate::configuration::AnalyzerScripts;
+use mclog::analyzer::dynamic::DynamicAnalyzer;
+use rhai::{Dynamic, Engine, EvalAltResult};
+use std::cell::Cell;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Operations between two looks at the clock, reading it on every operation is too slow
+const DEADLINE_CHECK_INTERVAL: u64 = 1_024;
+
+thread_local! {
+    /// When the script running on this thread has to stop
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Limit of [`AnalyzerScripts`] a script ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Operations,
+    CallLevels,
+    DataSize,
+    Timeout,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Operations => write!(f, "operations limit"),
+            Limit::CallLevels => write!(f, "call levels limit"),
+            Limit::DataSize => write!(f, "string, array or map size limit"),
+            Limit::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+impl Limit {
+    /// The limit behind the error, `None` when the script failed on its own
+    pub fn of(err: &EvalAltResult) -> Option<Self> {
+        match err.unwrap_inner() {
+            EvalAltResult::ErrorTooManyOperations(..) => Some(Limit::Operations),
+            EvalAltResult::ErrorStackOverflow(..) => Some(Limit::CallLevels),
+            EvalAltResult::ErrorDataTooLarge(..) => Some(Limit::DataSize),
+            EvalAltResult::ErrorTerminated(..) => Some(Limit::Timeout),
+            _ => None,
+        }
+    }
+}
+
+/// The [`DynamicAnalyzer`] engine with the limits applied.
+///
+/// mclog only hands out its engine together with the scripts, so building one compiles every
+/// script as well. It's built once and shared by all reloads of
+/// [`Scripts`](crate::scripts::Scripts).
+pub fn engine(limits: &AnalyzerScripts) -> Engine {
+    let mut engine = DynamicAnalyzer::default().engine;
+
+    engine
+        .set_max_operations(limits.max_operations)
+        .set_max_string_size(limits.max_string_size)
+        .set_max_array_size(limits.max_array_size)
+        .set_max_map_size(limits.max_map_size);
+
+    // unlike the others, 0 call levels would forbid calling functions at all
+    if limits.max_call_levels > 0 {
+        engine.set_max_call_levels(limits.max_call_levels);
+    }
+
+    if limits.timeout_in_milliseconds > 0 {
+        engine.on_progress(|operations| {
+            if operations % DEADLINE_CHECK_INTERVAL != 0 {
+                return None;
+            }
+
+            let deadline = DEADLINE.get()?;
+            (Instant::now() >= deadline).then_some(Dynamic::UNIT)
+        });
+    }
+
+    engine
+}
+
+/// Runs `f` with the timeout of [`AnalyzerScripts`] applied to scripts evaluated on this thread.
+pub fn with_timeout<T>(limits: &AnalyzerScripts, f: impl FnOnce() -> T) -> T {
+    let deadline = (limits.timeout_in_milliseconds > 0)
+        .then(|| Instant::now() + Duration::from_millis(limits.timeout_in_milliseconds));
+
+    let previous = DEADLINE.replace(deadline);
+    let result = f();
+    DEADLINE.set(previous);
+
+    result
+}
@@ -0,0 +1,79 @@
+use crate::scripts::{Match, Scripts};
+use mclog::analyzer::dynamic::chunks::Captures;
+use mclog::analyzer::DynamicAnalyzerDetails;
+use rhai::{Dynamic, Scope};
+use serde::Serialize;
+use std::time::Instant;
+
+/// What happened when a script ran, to find out why a detection did or didn't show up
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptTrace {
+    pub file: String,
+    /// [`ScriptPlatform::directory`](mclog::analyzer::dynamic::ScriptPlatform::directory)
+    /// the script was selected for
+    pub platform: String,
+    pub microseconds: u64,
+    /// What the script returned, `None` when it failed
+    pub result: Option<String>,
+    /// Detections made from the result, private ones included
+    pub matches: Vec<Match>,
+    /// The runtime error or exceeded limit, and identifiers without a detection
+    pub errors: Vec<String>,
+}
+
+impl Scripts {
+    /// Runs the scripts like [`Scripts::matches`], but keeps everything about every run.
+    pub fn trace(&self, dad: DynamicAnalyzerDetails) -> Vec<ScriptTrace> {
+        let scripts = self.applicable(&dad);
+
+        let mut scope = Scope::new();
+        scope.push_constant("dad", dad);
+
+        let mut traces = Vec::new();
+
+        for (platform, script) in scripts {
+            let started = Instant::now();
+            let result = self.eval(script, &mut scope);
+            let elapsed = started.elapsed();
+
+            let mut trace = ScriptTrace {
+                file: script.file.to_string_lossy().to_string(),
+                platform: platform.directory().to_string(),
+                microseconds: elapsed.as_micros() as u64,
+                result: None,
+                matches: Vec::new(),
+                errors: Vec::new(),
+            };
+
+            match result {
+                Ok(result) => {
+                    trace.result = Some(describe(&result));
+
+                    for matched in script.resolve(&result) {
+                        match matched {
+                            Ok(matched) => trace.matches.push(matched),
+                            Err(err) => trace.errors.push(err),
+                        }
+                    }
+                }
+                Err(err) => {
+                    script.report(&err);
+                    trace.errors.push(err.to_string());
+                }
+            }
+
+            traces.push(trace);
+        }
+
+        traces
+    }
+}
+
+fn describe(result: &Dynamic) -> String {
+    if let Some(captures) = result.read_lock::<Vec<Captures>>() {
+        return format!("{:?}", *captures);
+    }
+
+    format!("{result:?}")
+}
@@ -0,0 +1,248 @@
+use crate::scripts::Script;
+use rhai::{ASTFlags, ASTNode, Expr, FnCallExpr, Stmt, AST};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// Whether every identifier the script can return has a detection
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Validation {
+    pub identifiers: BTreeSet<String>,
+    /// Identifiers without a detection, returning them is an error at runtime
+    pub missing_detections: Vec<String>,
+    /// Detections the script never returns
+    pub unused_detections: Vec<String>,
+}
+
+impl Validation {
+    pub fn new(script: &Script) -> Self {
+        let identifiers = identifiers(&script.ast);
+
+        let missing_detections = identifiers
+            .iter()
+            .filter(|identifier| !script.detection.contains_key(*identifier))
+            .cloned()
+            .collect();
+
+        let mut unused_detections: Vec<String> = script
+            .detection
+            .keys()
+            .filter(|identifier| !identifiers.contains(*identifier))
+            .cloned()
+            .collect();
+        unused_detections.sort();
+
+        Self {
+            identifiers,
+            missing_detections,
+            unused_detections,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.missing_detections.is_empty()
+    }
+}
+
+/// Log functions that return one of their arguments as the identifier, with the position of
+/// it among the arguments after the log, e.g. `log.has_line("...", "Identifier")`
+const IDENTIFIER_ARGUMENTS: &[(&str, usize)] = &[("has_line", 1)];
+
+/// Identifiers the script returns as string literals, either as its value (`return "Identifier"`,
+/// the last expression or the branches of a trailing `if` or `switch`) or through a log function
+/// from [`IDENTIFIER_ARGUMENTS`]. Identifiers that are built at runtime can't be found.
+fn identifiers(ast: &AST) -> BTreeSet<String> {
+    let mut identifiers = BTreeSet::new();
+
+    tail_values(ast.statements(), &mut identifiers);
+
+    ast.walk(&mut |path: &[ASTNode]| {
+        match path.last() {
+            Some(ASTNode::Stmt(Stmt::Return(Some(value), flags, _)))
+                if !flags.contains(ASTFlags::BREAK) =>
+            {
+                values(value, &mut identifiers);
+            }
+            Some(ASTNode::Expr(Expr::MethodCall(call, _))) => {
+                identifier_argument(call, 0, &mut identifiers);
+            }
+            // `has_line(log, "...", "Identifier")`, the log is the first argument
+            Some(ASTNode::Expr(Expr::FnCall(call, _)) | ASTNode::Stmt(Stmt::FnCall(call, _))) => {
+                identifier_argument(call, 1, &mut identifiers);
+            }
+            _ => {}
+        }
+
+        true
+    });
+
+    identifiers
+}
+
+/// String literals the last statement of the block evaluates to
+fn tail_values(statements: &[Stmt], identifiers: &mut BTreeSet<String>) {
+    let Some(last) = statements.last() else {
+        return;
+    };
+
+    match last {
+        Stmt::Expr(value) => values(value, identifiers),
+        Stmt::If(flow, _) | Stmt::TryCatch(flow, _) => {
+            tail_values(flow.body.statements(), identifiers);
+            tail_values(flow.branch.statements(), identifiers);
+        }
+        Stmt::Switch(switch, _) => {
+            for case in &switch.1.expressions {
+                values(&case.rhs, identifiers);
+            }
+        }
+        Stmt::Block(block) => tail_values(block.statements(), identifiers),
+        _ => {}
+    }
+}
+
+/// String literals the expression evaluates to
+fn values(value: &Expr, identifiers: &mut BTreeSet<String>) {
+    match value {
+        Expr::StringConstant(value, _) => {
+            identifiers.insert(value.to_string());
+        }
+        Expr::Stmt(block) => tail_values(block.statements(), identifiers),
+        _ => {}
+    }
+}
+
+/// The identifier argument of a call to one of [`IDENTIFIER_ARGUMENTS`], `offset` is the
+/// number of arguments before the ones it counts from
+fn identifier_argument(call: &FnCallExpr, offset: usize, identifiers: &mut BTreeSet<String>) {
+    let Some((_, position)) = IDENTIFIER_ARGUMENTS
+        .iter()
+        .find(|(name, _)| call.name.as_str() == *name)
+    else {
+        return;
+    };
+
+    if let Some(Expr::StringConstant(value, _)) = call.args.get(position + offset) {
+        identifiers.insert(value.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripts::parse;
+    use rhai::Engine;
+    use std::path::Path;
+
+    /// Registers the custom operators of mclog, so the shipped scripts compile without it
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+        for operator in ["matchserver", "matchver"] {
+            engine
+                .register_custom_operator(operator, 140)
+                .expect("operator is a valid custom operator");
+        }
+
+        engine
+    }
+
+    fn identifiers_of(source: &str) -> BTreeSet<String> {
+        let ast = engine().compile(source).expect("source compiles");
+
+        identifiers(&ast)
+    }
+
+    fn set(identifiers: &[&str]) -> BTreeSet<String> {
+        identifiers
+            .iter()
+            .map(|identifier| identifier.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn shipped_scripts_are_valid() {
+        for (file, expected) in [
+            (
+                "scripts/bukkit/ambiguous_plugin_name.rhai",
+                "AmbiguousPluginName",
+            ),
+            ("scripts/forge/java_too_modern.rhai", "JavaTooModern"),
+            ("scripts/global/not_meowhost.rhai", "NotMeowHost"),
+        ] {
+            let file = Path::new(env!("CARGO_MANIFEST_DIR")).join(file);
+            let source = std::fs::read_to_string(&file).expect("script exists");
+            let script = parse(&engine(), &file, &source).expect("script compiles");
+
+            let validation = Validation::new(&script);
+
+            assert_eq!(
+                validation.identifiers,
+                set(&[expected]),
+                "{}",
+                file.display()
+            );
+            assert!(validation.is_valid(), "{}", file.display());
+            assert!(
+                validation.unused_detections.is_empty(),
+                "{}",
+                file.display()
+            );
+        }
+    }
+
+    #[test]
+    fn finds_the_last_expression() {
+        assert_eq!(identifiers_of(r#"let a = 1; "Tail""#), set(&["Tail"]));
+    }
+
+    #[test]
+    fn finds_branches_of_a_trailing_if() {
+        let source = r#"
+            let a = 1;
+            if a > 0 { "Positive" } else if a < 0 { "Negative" } else { "Zero" }
+        "#;
+
+        assert_eq!(
+            identifiers_of(source),
+            set(&["Negative", "Positive", "Zero"])
+        );
+    }
+
+    #[test]
+    fn finds_branches_of_a_trailing_switch() {
+        let source = r#"
+            let a = 1;
+            switch a { 1 => "One", 2 if a > 0 => "Two", _ => "Other" }
+        "#;
+
+        assert_eq!(identifiers_of(source), set(&["One", "Other", "Two"]));
+    }
+
+    #[test]
+    fn finds_returns_and_identifier_arguments() {
+        let source = r#"
+            let a = 1;
+            if a > 0 { return "Returned"; }
+            let result = log.has_line("Essentials {0}", "HasLine");
+            has_line(log, "Essentials {0}", "FnStyle");
+        "#;
+
+        assert_eq!(
+            identifiers_of(source),
+            set(&["FnStyle", "HasLine", "Returned"])
+        );
+    }
+
+    #[test]
+    fn ignores_other_string_arguments_and_statements() {
+        let source = r#"
+            let x = "Done";
+            let found = x.contains("Done");
+            let pattern = log.has_line("Essentials {0}", "Found");
+            "Unused";
+            if found { throw "Failed"; }
+        "#;
+
+        assert_eq!(identifiers_of(source), set(&["Found"]));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::configuration::Compression;
+use crate::storage::{PasteReader, PasteStore};
+use async_compression::tokio::bufread::{GzipDecoder, GzipEncoder, ZstdDecoder, ZstdEncoder};
+use async_trait::async_trait;
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use tracing::info;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Written in front of every stored paste, followed by a byte naming its [`Compression`].
+/// The content is whatever the uploader sent, so its compression is never guessed from it.
+const HEADER_MAGIC: [u8; 3] = *b"\0cc";
+const HEADER_LENGTH: usize = HEADER_MAGIC.len() + 1;
+
+/// Compresses pastes with the configured [`Compression`] before they reach the backend.
+///
+/// The compression is stored in a header with the paste, so pastes written with a different
+/// (or no) compression setting stay readable.
+pub struct CompressedStore {
+    inner: Arc<dyn PasteStore>,
+    compression: Compression,
+    /// Pastes stored before the header existed are decompressed up to this many bytes
+    size_limit: u64,
+}
+
+impl CompressedStore {
+    pub fn new(inner: Arc<dyn PasteStore>, compression: Compression, size_limit: u64) -> Self {
+        Self {
+            inner,
+            compression,
+            size_limit,
+        }
+    }
+
+    /// Rewrites pastes stored without compression or before the header existed using the
+    /// configured compression, keeping when they expire. Only the header of the others is read.
+    /// Returns the number of rewritten pastes.
+    pub async fn compress_uncompressed(&self) -> io::Result<usize> {
+        let mut compressed = 0;
+
+        for id in self.inner.list().await? {
+            let mut reader = self.inner.reader(&id).await?;
+
+            let rewrite = match parse_header(&read_header(&mut reader).await?) {
+                Some(Compression::None) => !matches!(self.compression, Compression::None),
+                Some(_) => false,
+                None => true,
+            };
+            if !rewrite {
+                continue;
+            }
+
+            let content = self.get(&id).await?;
+            self.replace(&id, &content).await?;
+            compressed += 1;
+        }
+
+        if compressed > 0 {
+            info!("Compressed {compressed} previously uncompressed pastes");
+        }
+
+        Ok(compressed)
+    }
+
+    fn header(&self) -> [u8; HEADER_LENGTH] {
+        let codec = match self.compression {
+            Compression::None => b'n',
+            Compression::Zstd => b'z',
+            Compression::Gzip => b'g',
+        };
+
+        let mut header = [0; HEADER_LENGTH];
+        header[..HEADER_MAGIC.len()].copy_from_slice(&HEADER_MAGIC);
+        header[HEADER_MAGIC.len()] = codec;
+
+        header
+    }
+
+    /// Decodes a paste stored before the header existed. Their compression can only be
+    /// detected from the content, so they're never decompressed beyond the size limit.
+    fn legacy_decoder(&self, header: Vec<u8>, reader: PasteReader) -> PasteReader {
+        let compression = detect(&header);
+        let reader: PasteReader = Box::new(std::io::Cursor::new(header).chain(reader));
+
+        match compression {
+            Some(_) => Box::new(decoder(reader, compression).take(self.size_limit)),
+            None => reader,
+        }
+    }
+}
+
+/// Reads the first [`HEADER_LENGTH`] bytes, fewer only if the paste is shorter.
+async fn read_header(reader: &mut PasteReader) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(HEADER_LENGTH);
+
+    (&mut *reader)
+        .take(HEADER_LENGTH as u64)
+        .read_to_end(&mut header)
+        .await?;
+
+    Ok(header)
+}
+
+/// Compression named by the header, `None` if the paste was stored before the header existed
+fn parse_header(header: &[u8]) -> Option<Compression> {
+    match header.strip_prefix(&HEADER_MAGIC)? {
+        b"n" => Some(Compression::None),
+        b"z" => Some(Compression::Zstd),
+        b"g" => Some(Compression::Gzip),
+        _ => None,
+    }
+}
+
+/// Compression of a paste stored before the header existed, detected from its magic number
+fn detect(content: &[u8]) -> Option<Compression> {
+    if content.starts_with(&ZSTD_MAGIC) {
+        Some(Compression::Zstd)
+    } else if content.starts_with(&GZIP_MAGIC) {
+        Some(Compression::Gzip)
+    } else {
+        None
+    }
+}
+
+fn decoder(reader: PasteReader, compression: Option<Compression>) -> PasteReader {
+    let reader = BufReader::new(reader);
+
+    match compression {
+        Some(Compression::Zstd) => {
+            let mut decoder = ZstdDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        Some(Compression::Gzip) => {
+            let mut decoder = GzipDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        Some(Compression::None) | None => Box::new(reader),
+    }
+}
+
+#[async_trait]
+impl PasteStore for CompressedStore {
+    async fn put(&self, id: &str, mut content: &[u8]) -> io::Result<()> {
+        self.put_stream(id, &mut content).await?;
+
+        Ok(())
+    }
+
+    async fn put_stream(
+        &self,
+        id: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> io::Result<u64> {
+        let header = self.header();
+        let reader = BufReader::new(reader);
+
+        match self.compression {
+            Compression::None => self.inner.put_stream(id, &mut header.chain(reader)).await,
+            Compression::Zstd => {
+                self.inner
+                    .put_stream(id, &mut header.chain(ZstdEncoder::new(reader)))
+                    .await
+            }
+            Compression::Gzip => {
+                self.inner
+                    .put_stream(id, &mut header.chain(GzipEncoder::new(reader)))
+                    .await
+            }
+        }
+    }
+
+    async fn replace(&self, id: &str, content: &[u8]) -> io::Result<()> {
+        let mut compressed = self.header().to_vec();
+
+        match self.compression {
+            Compression::None => compressed.extend_from_slice(content),
+            Compression::Zstd => {
+                ZstdEncoder::new(content)
+                    .read_to_end(&mut compressed)
+                    .await?;
+            }
+            Compression::Gzip => {
+                GzipEncoder::new(content)
+                    .read_to_end(&mut compressed)
+                    .await?;
+            }
+        }
+
+        self.inner.replace(id, &compressed).await
+    }
+
+    async fn get(&self, id: &str) -> io::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        self.reader(id).await?.read_to_end(&mut content).await?;
+
+        Ok(content)
+    }
+
+    async fn reader(&self, id: &str) -> io::Result<PasteReader> {
+        let mut reader = self.inner.reader(id).await?;
+        let header = read_header(&mut reader).await?;
+
+        Ok(match parse_header(&header) {
+            Some(compression) => decoder(reader, Some(compression)),
+            None => self.legacy_decoder(header, reader),
+        })
+    }
+
+    async fn delete(&self, id: &str) -> io::Result<()> {
+        self.inner.delete(id).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        self.inner.rename(from, to).await
+    }
+
+    async fn exists(&self, id: &str) -> io::Result<bool> {
+        self.inner.exists(id).await
+    }
+
+    async fn expire(&self, id: &str, expires_at: Option<u64>) -> io::Result<()> {
+        self.inner.expire(id, expires_at).await
+    }
+
+    async fn size(&self, id: &str) -> io::Result<u64> {
+        self.inner.size(id).await
+    }
+
+    async fn list(&self) -> io::Result<Vec<String>> {
+        self.inner.list().await
+    }
+}
@@ -0,0 +1,664 @@
+use crate::storage::compression::CompressedStore;
+use crate::storage::refs::BlobRefs;
+use crate::storage::{PasteReader, PasteStore};
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Namespace of the backend shared content is stored in, named after the SHA-256 of the content
+pub const BLOBS_NAMESPACE: &str = ".blobs";
+
+/// Stores identical pastes only once.
+///
+/// Every paste id refers to a blob named after the hash of its content, see [`BlobRefs`].
+/// Blobs are kept apart from the pastes, so they can't be reached through a paste id.
+/// The blob is removed together with the last paste referring to it. References expire with
+/// their paste, blobs nobody refers to anymore are left to the [`Sweeper`](crate::sweeper::Sweeper).
+pub struct DedupStore {
+    pastes: Arc<CompressedStore>,
+    blobs: Arc<CompressedStore>,
+    refs: Arc<dyn BlobRefs>,
+    enabled: bool,
+}
+
+impl DedupStore {
+    /// When it's not `enabled`, new pastes are stored as they are, but pastes that were
+    /// deduplicated before stay readable.
+    pub fn new(
+        pastes: Arc<CompressedStore>,
+        blobs: Arc<CompressedStore>,
+        refs: Arc<dyn BlobRefs>,
+        enabled: bool,
+    ) -> Self {
+        Self {
+            pastes,
+            blobs,
+            refs,
+            enabled,
+        }
+    }
+
+    /// Compresses the stored pastes and blobs, see [`CompressedStore::compress_uncompressed`].
+    pub async fn compress_uncompressed(&self) -> io::Result<usize> {
+        Ok(self.pastes.compress_uncompressed().await? + self.blobs.compress_uncompressed().await?)
+    }
+
+    /// Blobs nobody refers to, including ones still being uploaded and ones whose pastes
+    /// all expired
+    pub async fn unreferenced_blobs(&self) -> io::Result<Vec<String>> {
+        let mut unreferenced = Vec::new();
+
+        for blob in self.blobs.list().await? {
+            if self.refs.prune(&blob).await?.is_empty() {
+                unreferenced.push(blob);
+            }
+        }
+
+        Ok(unreferenced)
+    }
+
+    /// Removes a blob unless a paste refers to it by now. Returns the freed bytes.
+    pub async fn remove_blob(&self, blob: &str) -> io::Result<Option<u64>> {
+        self.locked(blob, async {
+            if !self.refs.prune(blob).await?.is_empty() {
+                return Ok(None);
+            }
+
+            let size = self.blobs.size(blob).await?;
+            self.blobs.delete(blob).await?;
+
+            Ok(Some(size))
+        })
+        .await
+    }
+
+    /// Blob the content of a new paste is streamed to before its hash is known
+    fn upload_blob(id: &str) -> String {
+        format!("upload-{id}")
+    }
+
+    /// Store and key the content of the paste is stored under
+    async fn locate(&self, id: &str) -> io::Result<(&CompressedStore, String)> {
+        Ok(match self.refs.hash(id).await? {
+            Some(hash) => (&self.blobs, hash),
+            None => (&self.pastes, id.to_string()),
+        })
+    }
+
+    /// Runs `action` while holding the lock of the blob of `hash`, so the blob isn't stored
+    /// and removed at the same time.
+    async fn locked<T>(
+        &self,
+        hash: &str,
+        action: impl Future<Output = io::Result<T>>,
+    ) -> io::Result<T> {
+        let token = self.refs.lock(hash).await?;
+        let result = action.await;
+        self.refs.unlock(hash, &token).await?;
+
+        result
+    }
+
+    /// Points `id` to the blob of `hash`, its content was just written to the `upload` blob.
+    async fn link(&self, id: &str, hash: &str, upload: &str) -> io::Result<()> {
+        self.locked(hash, async {
+            self.refs.link(id, hash).await?;
+
+            if self.blobs.exists(hash).await? {
+                self.blobs.delete(upload).await
+            } else {
+                self.blobs.rename(upload, hash).await
+            }
+        })
+        .await
+    }
+
+    /// Removes the reference of `id` and the blob, if it was the last one.
+    async fn unlink(&self, id: &str, hash: &str) -> io::Result<()> {
+        self.locked(hash, async {
+            if self.refs.unlink(id, hash).await? == Some(0) {
+                self.blobs.delete(hash).await?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Hashes everything read through it
+struct HashingReader<'a> {
+    reader: &'a mut (dyn AsyncRead + Send + Unpin),
+    hasher: Sha256,
+}
+
+impl HashingReader<'_> {
+    fn finish(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+}
+
+impl AsyncRead for HashingReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+
+        let poll = Pin::new(&mut *this.reader).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = poll {
+            this.hasher.update(&buf.filled()[filled..]);
+        }
+
+        poll
+    }
+}
+
+#[async_trait]
+impl PasteStore for DedupStore {
+    async fn put(&self, id: &str, mut content: &[u8]) -> io::Result<()> {
+        self.put_stream(id, &mut content).await?;
+
+        Ok(())
+    }
+
+    async fn put_stream(
+        &self,
+        id: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> io::Result<u64> {
+        if self.refs.hash(id).await?.is_some() {
+            self.delete(id).await?;
+        }
+
+        if !self.enabled {
+            return self.pastes.put_stream(id, reader).await;
+        }
+
+        let mut reader = HashingReader {
+            reader,
+            hasher: Sha256::new(),
+        };
+        let upload = Self::upload_blob(id);
+        let written = self.blobs.put_stream(&upload, &mut reader).await?;
+
+        self.link(id, &reader.finish(), &upload).await?;
+
+        Ok(written)
+    }
+
+    async fn get(&self, id: &str) -> io::Result<Vec<u8>> {
+        let (store, key) = self.locate(id).await?;
+        store.get(&key).await
+    }
+
+    async fn reader(&self, id: &str) -> io::Result<PasteReader> {
+        let (store, key) = self.locate(id).await?;
+        store.reader(&key).await
+    }
+
+    async fn delete(&self, id: &str) -> io::Result<()> {
+        match self.refs.hash(id).await? {
+            Some(hash) => self.unlink(id, &hash).await,
+            None => self.pastes.delete(id).await,
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let Some(hash) = self.refs.hash(from).await? else {
+            return self.pastes.rename(from, to).await;
+        };
+
+        if self.refs.hash(to).await?.is_some() {
+            self.delete(to).await?;
+        }
+
+        // the number of references doesn't change, so the blob isn't touched
+        if !self.refs.relink(from, to, &hash).await? {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Paste '{from}' was changed while it was renamed"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn exists(&self, id: &str) -> io::Result<bool> {
+        let (store, key) = self.locate(id).await?;
+        store.exists(&key).await
+    }
+
+    /// Shared blobs never expire, the reference to it does. The blob is removed once no
+    /// paste refers to it, see [`DedupStore::unreferenced_blobs`].
+    async fn expire(&self, id: &str, expires_at: Option<u64>) -> io::Result<()> {
+        match self.refs.hash(id).await? {
+            Some(_) => self.refs.expire(id, expires_at).await,
+            None => self.pastes.expire(id, expires_at).await,
+        }
+    }
+
+    /// Size of the content the paste refers to, shared blobs are counted for every paste.
+    async fn size(&self, id: &str) -> io::Result<u64> {
+        let (store, key) = self.locate(id).await?;
+        store.size(&key).await
+    }
+
+    /// Lists the ids referring to a blob instead of the blob itself, see
+    /// [`DedupStore::unreferenced_blobs`] for the others. Expired references aren't listed.
+    async fn list(&self) -> io::Result<Vec<String>> {
+        let mut ids = self.pastes.list().await?;
+
+        for blob in self.blobs.list().await? {
+            ids.extend(self.refs.prune(&blob).await?);
+        }
+
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Compression;
+    use crate::paste::metadata::hash;
+    use std::collections::{BTreeSet, HashMap, HashSet};
+    use std::sync::Mutex;
+    use tokio::io::AsyncReadExt;
+
+    const SIZE_LIMIT: u64 = 1024;
+
+    #[derive(Default)]
+    struct MemoryStore {
+        pastes: Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    impl MemoryStore {
+        fn keys(&self) -> BTreeSet<String> {
+            self.pastes.lock().unwrap().keys().cloned().collect()
+        }
+    }
+
+    fn not_found(id: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, format!("Paste '{id}' not found"))
+    }
+
+    #[async_trait]
+    impl PasteStore for MemoryStore {
+        async fn put(&self, id: &str, content: &[u8]) -> io::Result<()> {
+            self.pastes
+                .lock()
+                .unwrap()
+                .insert(id.to_string(), content.to_vec());
+
+            Ok(())
+        }
+
+        async fn put_stream(
+            &self,
+            id: &str,
+            reader: &mut (dyn AsyncRead + Send + Unpin),
+        ) -> io::Result<u64> {
+            let mut content = Vec::new();
+            let written = reader.read_to_end(&mut content).await?;
+            self.put(id, &content).await?;
+
+            Ok(written as u64)
+        }
+
+        async fn get(&self, id: &str) -> io::Result<Vec<u8>> {
+            self.pastes
+                .lock()
+                .unwrap()
+                .get(id)
+                .cloned()
+                .ok_or_else(|| not_found(id))
+        }
+
+        async fn reader(&self, id: &str) -> io::Result<PasteReader> {
+            Ok(Box::new(std::io::Cursor::new(self.get(id).await?)))
+        }
+
+        async fn delete(&self, id: &str) -> io::Result<()> {
+            self.pastes
+                .lock()
+                .unwrap()
+                .remove(id)
+                .map(|_| ())
+                .ok_or_else(|| not_found(id))
+        }
+
+        async fn exists(&self, id: &str) -> io::Result<bool> {
+            Ok(self.pastes.lock().unwrap().contains_key(id))
+        }
+
+        async fn size(&self, id: &str) -> io::Result<u64> {
+            Ok(self.get(id).await?.len() as u64)
+        }
+
+        async fn list(&self) -> io::Result<Vec<String>> {
+            Ok(self.keys().into_iter().collect())
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryRefs {
+        hashes: Mutex<HashMap<String, String>>,
+        refs: Mutex<HashMap<String, BTreeSet<String>>>,
+        locks: Mutex<HashSet<String>>,
+    }
+
+    impl MemoryRefs {
+        /// Drops the reference of `id` like Redis does once it expired
+        fn expire_now(&self, id: &str) {
+            self.hashes.lock().unwrap().remove(id);
+        }
+    }
+
+    #[async_trait]
+    impl BlobRefs for MemoryRefs {
+        async fn hash(&self, id: &str) -> io::Result<Option<String>> {
+            Ok(self.hashes.lock().unwrap().get(id).cloned())
+        }
+
+        async fn link(&self, id: &str, hash: &str) -> io::Result<()> {
+            self.hashes
+                .lock()
+                .unwrap()
+                .insert(id.to_string(), hash.to_string());
+            self.refs
+                .lock()
+                .unwrap()
+                .entry(hash.to_string())
+                .or_default()
+                .insert(id.to_string());
+
+            Ok(())
+        }
+
+        async fn unlink(&self, id: &str, hash: &str) -> io::Result<Option<u64>> {
+            let mut hashes = self.hashes.lock().unwrap();
+            if hashes.get(id).map(String::as_str) != Some(hash) {
+                return Ok(None);
+            }
+            hashes.remove(id);
+
+            let mut refs = self.refs.lock().unwrap();
+            let ids = refs.entry(hash.to_string()).or_default();
+            ids.remove(id);
+
+            Ok(Some(ids.len() as u64))
+        }
+
+        async fn relink(&self, from: &str, to: &str, hash: &str) -> io::Result<bool> {
+            if self.unlink(from, hash).await?.is_none() {
+                return Ok(false);
+            }
+            self.link(to, hash).await?;
+
+            Ok(true)
+        }
+
+        /// Expiring references are dropped by [`MemoryRefs::expire_now`]
+        async fn expire(&self, _id: &str, _expires_at: Option<u64>) -> io::Result<()> {
+            Ok(())
+        }
+
+        async fn prune(&self, hash: &str) -> io::Result<Vec<String>> {
+            let hashes = self.hashes.lock().unwrap();
+            let mut refs = self.refs.lock().unwrap();
+            let ids = refs.entry(hash.to_string()).or_default();
+            ids.retain(|id| hashes.get(id).map(String::as_str) == Some(hash));
+
+            Ok(ids.iter().cloned().collect())
+        }
+
+        async fn lock(&self, hash: &str) -> io::Result<String> {
+            while !self.locks.lock().unwrap().insert(hash.to_string()) {
+                tokio::task::yield_now().await;
+            }
+
+            Ok(hash.to_string())
+        }
+
+        async fn unlock(&self, hash: &str, _token: &str) -> io::Result<()> {
+            self.locks.lock().unwrap().remove(hash);
+
+            Ok(())
+        }
+    }
+
+    struct Fixture {
+        store: DedupStore,
+        pastes: Arc<MemoryStore>,
+        blobs: Arc<MemoryStore>,
+        refs: Arc<MemoryRefs>,
+    }
+
+    fn fixture(enabled: bool) -> Fixture {
+        let pastes = Arc::new(MemoryStore::default());
+        let blobs = Arc::new(MemoryStore::default());
+        let refs = Arc::new(MemoryRefs::default());
+
+        let store = DedupStore::new(
+            Arc::new(CompressedStore::new(
+                pastes.clone(),
+                Compression::Zstd,
+                SIZE_LIMIT,
+            )),
+            Arc::new(CompressedStore::new(
+                blobs.clone(),
+                Compression::Zstd,
+                SIZE_LIMIT,
+            )),
+            refs.clone(),
+            enabled,
+        );
+
+        Fixture {
+            store,
+            pastes,
+            blobs,
+            refs,
+        }
+    }
+
+    #[tokio::test]
+    async fn put_stores_identical_content_once() {
+        let Fixture {
+            store,
+            pastes,
+            blobs,
+            ..
+        } = fixture(true);
+
+        store.put("a", b"crash").await.unwrap();
+        store.put("b", b"crash").await.unwrap();
+
+        assert_eq!(store.get("a").await.unwrap(), b"crash");
+        assert_eq!(store.get("b").await.unwrap(), b"crash");
+        assert!(pastes.keys().is_empty());
+        assert_eq!(blobs.keys(), BTreeSet::from([hash(b"crash")]));
+        assert!(store.unreferenced_blobs().await.unwrap().is_empty());
+
+        let mut ids = store.list().await.unwrap();
+        ids.sort();
+        assert_eq!(ids, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn put_without_deduplication_stores_pastes_as_they_are() {
+        let Fixture {
+            store,
+            pastes,
+            blobs,
+            ..
+        } = fixture(false);
+
+        store.put("a", b"crash").await.unwrap();
+        store.put("b", b"crash").await.unwrap();
+
+        assert_eq!(store.get("b").await.unwrap(), b"crash");
+        assert_eq!(pastes.keys(), BTreeSet::from(["a".into(), "b".into()]));
+        assert!(blobs.keys().is_empty());
+    }
+
+    #[tokio::test]
+    async fn put_replaces_the_reference_of_an_existing_paste() {
+        let Fixture { store, blobs, .. } = fixture(true);
+
+        store.put("a", b"crash").await.unwrap();
+        store.put("a", b"fixed").await.unwrap();
+
+        assert_eq!(store.get("a").await.unwrap(), b"fixed");
+        assert_eq!(blobs.keys(), BTreeSet::from([hash(b"fixed")]));
+    }
+
+    #[tokio::test]
+    async fn delete_removes_the_blob_with_the_last_reference() {
+        let Fixture { store, blobs, .. } = fixture(true);
+
+        store.put("a", b"crash").await.unwrap();
+        store.put("b", b"crash").await.unwrap();
+
+        store.delete("a").await.unwrap();
+        assert!(!store.exists("a").await.unwrap());
+        assert_eq!(store.get("b").await.unwrap(), b"crash");
+        assert_eq!(blobs.keys().len(), 1);
+
+        store.delete("b").await.unwrap();
+        assert!(blobs.keys().is_empty());
+    }
+
+    #[tokio::test]
+    async fn link_after_the_last_reference_was_deleted_stores_the_blob_again() {
+        let Fixture { store, blobs, .. } = fixture(true);
+
+        store.put("a", b"crash").await.unwrap();
+        store.delete("a").await.unwrap();
+        store.put("b", b"crash").await.unwrap();
+
+        assert_eq!(store.get("b").await.unwrap(), b"crash");
+        assert_eq!(blobs.keys(), BTreeSet::from([hash(b"crash")]));
+    }
+
+    #[tokio::test]
+    async fn rename_moves_the_reference() {
+        let Fixture { store, blobs, .. } = fixture(true);
+
+        store.put("a", b"crash").await.unwrap();
+        store.rename("a", "b").await.unwrap();
+
+        assert!(!store.exists("a").await.unwrap());
+        assert_eq!(store.get("b").await.unwrap(), b"crash");
+        assert_eq!(store.list().await.unwrap(), ["b"]);
+
+        store.delete("b").await.unwrap();
+        assert!(blobs.keys().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rename_replaces_the_target() {
+        let Fixture { store, blobs, .. } = fixture(true);
+
+        store.put("a", b"crash").await.unwrap();
+        store.put("b", b"fixed").await.unwrap();
+        store.rename("a", "b").await.unwrap();
+
+        assert_eq!(store.get("b").await.unwrap(), b"crash");
+        assert_eq!(blobs.keys(), BTreeSet::from([hash(b"crash")]));
+    }
+
+    #[tokio::test]
+    async fn blobs_are_not_reachable_through_paste_ids() {
+        let Fixture { store, .. } = fixture(true);
+
+        store.put("a", b"crash").await.unwrap();
+        let hash = hash(b"crash");
+
+        assert!(!store.exists(&hash).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn remove_blob_keeps_referenced_blobs() {
+        let Fixture { store, blobs, .. } = fixture(true);
+
+        store.put("a", b"crash").await.unwrap();
+        blobs.put("upload-b", b"half").await.unwrap();
+
+        assert_eq!(store.unreferenced_blobs().await.unwrap(), ["upload-b"]);
+        assert_eq!(store.remove_blob(&hash(b"crash")).await.unwrap(), None);
+        assert!(store.remove_blob("upload-b").await.unwrap().is_some());
+        assert_eq!(blobs.keys(), BTreeSet::from([hash(b"crash")]));
+    }
+
+    #[tokio::test]
+    async fn expired_references_are_dropped() {
+        let Fixture {
+            store, blobs, refs, ..
+        } = fixture(true);
+
+        store.put("a", b"crash").await.unwrap();
+        store.put("b", b"crash").await.unwrap();
+
+        refs.expire_now("a");
+        assert_eq!(store.list().await.unwrap(), ["b"]);
+        assert!(store.unreferenced_blobs().await.unwrap().is_empty());
+
+        refs.expire_now("b");
+        assert!(store.list().await.unwrap().is_empty());
+        assert_eq!(store.unreferenced_blobs().await.unwrap(), [hash(b"crash")]);
+        assert!(store.remove_blob(&hash(b"crash")).await.unwrap().is_some());
+        assert!(blobs.keys().is_empty());
+    }
+
+    async fn zstd(content: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        async_compression::tokio::bufread::ZstdEncoder::new(content)
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+
+        compressed
+    }
+
+    #[tokio::test]
+    async fn uploaded_compressed_content_is_not_decompressed() {
+        let pastes = Arc::new(MemoryStore::default());
+        let store = CompressedStore::new(pastes.clone(), Compression::None, SIZE_LIMIT);
+
+        let frame = zstd(b"crash").await;
+        store.put("a", &frame).await.unwrap();
+
+        assert_eq!(store.get("a").await.unwrap(), frame);
+    }
+
+    #[tokio::test]
+    async fn pastes_stored_without_a_header_stay_readable() {
+        let pastes = Arc::new(MemoryStore::default());
+        let store = CompressedStore::new(pastes.clone(), Compression::Gzip, SIZE_LIMIT);
+
+        pastes.put("plain", b"crash").await.unwrap();
+        pastes.put("zstd", &zstd(b"crash").await).await.unwrap();
+        pastes
+            .put("bomb", &zstd(&[0; SIZE_LIMIT as usize * 4]).await)
+            .await
+            .unwrap();
+
+        assert_eq!(store.get("plain").await.unwrap(), b"crash");
+        assert_eq!(store.get("zstd").await.unwrap(), b"crash");
+        assert_eq!(store.get("bomb").await.unwrap().len(), SIZE_LIMIT as usize);
+
+        assert_eq!(store.compress_uncompressed().await.unwrap(), 3);
+        assert_eq!(store.compress_uncompressed().await.unwrap(), 0);
+        assert_eq!(store.get("zstd").await.unwrap(), b"crash");
+    }
+}
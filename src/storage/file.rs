@@ -0,0 +1,99 @@
+use crate::storage::{PasteReader, PasteStore};
+use async_trait::async_trait;
+use std::io;
+use std::path::PathBuf;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncWriteExt, BufWriter};
+
+pub struct FileStore {
+    directory: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        // ids come straight from the URL, don't let them escape the data directory
+        // or reach the blobs directory
+        if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid paste id '{id}'"),
+            ));
+        }
+
+        Ok(self.directory.join(id))
+    }
+}
+
+#[async_trait]
+impl PasteStore for FileStore {
+    async fn put(&self, id: &str, content: &[u8]) -> io::Result<()> {
+        let path = self.path(id)?;
+        // write next to the paste first so an existing paste is never left half written
+        let temporary_path = self.directory.join(format!(".{id}.tmp"));
+
+        tokio::fs::write(&temporary_path, content).await?;
+        tokio::fs::rename(temporary_path, path).await
+    }
+
+    async fn put_stream(
+        &self,
+        id: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> io::Result<u64> {
+        let mut file = BufWriter::new(File::create(self.path(id)?).await?);
+
+        let written = tokio::io::copy(reader, &mut file).await?;
+        file.flush().await?;
+
+        Ok(written)
+    }
+
+    async fn get(&self, id: &str) -> io::Result<Vec<u8>> {
+        tokio::fs::read(self.path(id)?).await
+    }
+
+    async fn reader(&self, id: &str) -> io::Result<PasteReader> {
+        let file = File::open(self.path(id)?).await?;
+
+        Ok(Box::new(file))
+    }
+
+    async fn delete(&self, id: &str) -> io::Result<()> {
+        tokio::fs::remove_file(self.path(id)?).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        tokio::fs::rename(self.path(from)?, self.path(to)?).await
+    }
+
+    async fn exists(&self, id: &str) -> io::Result<bool> {
+        tokio::fs::try_exists(self.path(id)?).await
+    }
+
+    async fn size(&self, id: &str) -> io::Result<u64> {
+        Ok(tokio::fs::metadata(self.path(id)?).await?.len())
+    }
+
+    async fn list(&self) -> io::Result<Vec<String>> {
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+        let mut ids = Vec::new();
+
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+
+            if let Some(id) = entry.file_name().to_str() {
+                if !id.starts_with('.') {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+
+        Ok(ids)
+    }
+}
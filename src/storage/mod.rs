@@ -0,0 +1,130 @@
+use crate::configuration::{Settings, StorageMethod};
+use crate::routes::post::get_expiration;
+use crate::storage::compression::CompressedStore;
+use crate::storage::dedup::{DedupStore, BLOBS_NAMESPACE};
+use crate::storage::file::FileStore;
+use crate::storage::redis::RedisStore;
+use crate::storage::refs::RedisBlobRefs;
+use crate::storage::s3::S3Store;
+use crate::CURRENT_DIRECTORY;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use fred::clients::RedisPool;
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader, Lines};
+
+pub mod compression;
+pub mod dedup;
+pub mod file;
+pub mod redis;
+pub mod refs;
+pub mod s3;
+
+pub type PasteReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type PasteLines = Lines<BufReader<PasteReader>>;
+
+/// Backend that keeps paste bodies, addressed by paste id.
+#[async_trait]
+pub trait PasteStore: Send + Sync {
+    async fn put(&self, id: &str, content: &[u8]) -> io::Result<()>;
+
+    /// Writes everything from `reader` under `id` and returns the number of bytes written.
+    async fn put_stream(
+        &self,
+        id: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> io::Result<u64>;
+
+    /// Overwrites an existing paste, keeping when it expires.
+    async fn replace(&self, id: &str, content: &[u8]) -> io::Result<()> {
+        self.put(id, content).await
+    }
+
+    async fn get(&self, id: &str) -> io::Result<Vec<u8>>;
+
+    async fn reader(&self, id: &str) -> io::Result<PasteReader>;
+
+    async fn lines(&self, id: &str) -> io::Result<PasteLines> {
+        let reader = self.reader(id).await?;
+
+        Ok(BufReader::new(reader).lines())
+    }
+
+    async fn delete(&self, id: &str) -> io::Result<()>;
+
+    /// Moves a paste to another id, replacing whatever was stored there.
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let content = self.get(from).await?;
+        self.put(to, &content).await?;
+
+        self.delete(from).await
+    }
+
+    async fn exists(&self, id: &str) -> io::Result<bool>;
+
+    /// Makes the paste expire at the given unix timestamp, or never for `None`.
+    /// Backends without native expiration leave this to the [`Sweeper`](crate::sweeper::Sweeper).
+    async fn expire(&self, _id: &str, _expires_at: Option<u64>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Size of the stored paste in bytes, after compression.
+    async fn size(&self, id: &str) -> io::Result<u64>;
+
+    async fn list(&self) -> io::Result<Vec<String>>;
+}
+
+/// Builds the configured backend. It's always wrapped in [`CompressedStore`] and [`DedupStore`]
+/// so pastes are (de)compressed and deduplicated in one place regardless of where they're stored.
+/// Deduplicated content is kept in [`BLOBS_NAMESPACE`] of the backend.
+pub fn get_store(
+    configuration: &Settings,
+    redis_pool: &RedisPool,
+) -> anyhow::Result<Arc<DedupStore>> {
+    let (pastes, blobs): (Arc<dyn PasteStore>, Arc<dyn PasteStore>) =
+        match configuration.storage.method {
+            StorageMethod::File => {
+                let directory = CURRENT_DIRECTORY.join(&configuration.storage.directory);
+
+                (
+                    Arc::new(FileStore::new(directory.clone())),
+                    Arc::new(FileStore::new(directory.join(BLOBS_NAMESPACE))),
+                )
+            }
+            StorageMethod::S3 => {
+                let s3 =
+                    configuration.storage.s3.as_ref().ok_or_else(|| {
+                        anyhow!("Storage method is 's3' but [storage.s3] is missing")
+                    })?;
+
+                (
+                    Arc::new(S3Store::new(s3)?),
+                    Arc::new(S3Store::new(s3)?.namespaced(BLOBS_NAMESPACE)),
+                )
+            }
+            // shared blobs never expire on their own
+            StorageMethod::Redis => (
+                Arc::new(RedisStore::new(
+                    redis_pool.clone(),
+                    get_expiration(configuration),
+                )),
+                Arc::new(RedisStore::new(redis_pool.clone(), None).namespaced(BLOBS_NAMESPACE)),
+            ),
+        };
+
+    let compressed = |store| {
+        Arc::new(CompressedStore::new(
+            store,
+            configuration.storage.compression,
+            configuration.paste.size_limit as u64,
+        ))
+    };
+
+    Ok(Arc::new(DedupStore::new(
+        compressed(pastes),
+        compressed(blobs),
+        Arc::new(RedisBlobRefs::new(redis_pool.clone())),
+        configuration.storage.deduplicate,
+    )))
+}
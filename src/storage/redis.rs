@@ -0,0 +1,183 @@
+use crate::storage::{PasteReader, PasteStore};
+use async_trait::async_trait;
+use axum::body::Bytes;
+use fred::clients::RedisPool;
+use fred::error::RedisError;
+use fred::interfaces::{KeysInterface, SetsInterface};
+use fred::types::{Expiration, RedisValue};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Set of ids stored by [`RedisStore`], used for listing
+const INDEX_KEY: &str = "pastes";
+
+/// Keeps paste bodies in Redis under `<id>:content`, right next to the paste type key.
+/// Stores in a namespace use `<namespace>:<id>:content` instead.
+pub struct RedisStore {
+    pool: RedisPool,
+    expiration: Option<Expiration>,
+    namespace: Option<&'static str>,
+}
+
+impl RedisStore {
+    pub fn new(pool: RedisPool, expiration: Option<Expiration>) -> Self {
+        Self {
+            pool,
+            expiration,
+            namespace: None,
+        }
+    }
+
+    /// Moves the store to keys that can't be reached through the ids of a store without one
+    pub fn namespaced(mut self, namespace: &'static str) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    fn key(&self, id: &str) -> io::Result<String> {
+        // ids come straight from the URL, don't let them reach other keys
+        if id.contains(':') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid paste id '{id}'"),
+            ));
+        }
+
+        Ok(match self.namespace {
+            Some(namespace) => format!("{namespace}:{id}:content"),
+            None => format!("{id}:content"),
+        })
+    }
+
+    fn index_key(&self) -> String {
+        match self.namespace {
+            Some(namespace) => format!("{namespace}:{INDEX_KEY}"),
+            None => INDEX_KEY.to_string(),
+        }
+    }
+}
+
+fn redis_error(err: RedisError) -> io::Error {
+    io::Error::other(err)
+}
+
+#[async_trait]
+impl PasteStore for RedisStore {
+    async fn put(&self, id: &str, content: &[u8]) -> io::Result<()> {
+        let _: () = self
+            .pool
+            .set(
+                self.key(id)?,
+                RedisValue::Bytes(Bytes::copy_from_slice(content)),
+                self.expiration.clone(),
+                None,
+                false,
+            )
+            .await
+            .map_err(redis_error)?;
+
+        let _: () = self
+            .pool
+            .sadd(self.index_key(), id)
+            .await
+            .map_err(redis_error)?;
+
+        Ok(())
+    }
+
+    async fn replace(&self, id: &str, content: &[u8]) -> io::Result<()> {
+        let _: () = self
+            .pool
+            .set(
+                self.key(id)?,
+                RedisValue::Bytes(Bytes::copy_from_slice(content)),
+                Some(Expiration::KEEPTTL),
+                None,
+                false,
+            )
+            .await
+            .map_err(redis_error)?;
+
+        Ok(())
+    }
+
+    async fn put_stream(
+        &self,
+        id: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> io::Result<u64> {
+        let mut content = Vec::new();
+        let written = reader.read_to_end(&mut content).await?;
+
+        self.put(id, &content).await?;
+
+        Ok(written as u64)
+    }
+
+    async fn get(&self, id: &str) -> io::Result<Vec<u8>> {
+        let content: Option<Vec<u8>> = self.pool.get(self.key(id)?).await.map_err(redis_error)?;
+
+        content.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Paste '{id}' not found"))
+        })
+    }
+
+    async fn reader(&self, id: &str) -> io::Result<PasteReader> {
+        let content = self.get(id).await?;
+
+        Ok(Box::new(std::io::Cursor::new(content)))
+    }
+
+    async fn delete(&self, id: &str) -> io::Result<()> {
+        let _: () = self.pool.del(self.key(id)?).await.map_err(redis_error)?;
+        let _: () = self
+            .pool
+            .srem(self.index_key(), id)
+            .await
+            .map_err(redis_error)?;
+
+        Ok(())
+    }
+
+    async fn exists(&self, id: &str) -> io::Result<bool> {
+        self.pool.exists(self.key(id)?).await.map_err(redis_error)
+    }
+
+    async fn expire(&self, id: &str, expires_at: Option<u64>) -> io::Result<()> {
+        let _: () = match expires_at {
+            Some(expires_at) => self.pool.expire_at(self.key(id)?, expires_at as i64).await,
+            None => self.pool.persist(self.key(id)?).await,
+        }
+        .map_err(redis_error)?;
+
+        Ok(())
+    }
+
+    async fn size(&self, id: &str) -> io::Result<u64> {
+        self.pool.strlen(self.key(id)?).await.map_err(redis_error)
+    }
+
+    async fn list(&self) -> io::Result<Vec<String>> {
+        let indexed: Vec<String> = self
+            .pool
+            .smembers(self.index_key())
+            .await
+            .map_err(redis_error)?;
+        let mut ids = Vec::new();
+
+        for id in indexed {
+            if self.exists(&id).await? {
+                ids.push(id);
+            } else {
+                // content expired together with the paste type, drop it from the index too
+                let _: () = self
+                    .pool
+                    .srem(self.index_key(), id)
+                    .await
+                    .map_err(redis_error)?;
+            }
+        }
+
+        Ok(ids)
+    }
+}
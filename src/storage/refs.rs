@@ -0,0 +1,240 @@
+use crate::routes::post::gen_id;
+use async_trait::async_trait;
+use fred::clients::RedisPool;
+use fred::error::RedisError;
+use fred::interfaces::{KeysInterface, LuaInterface};
+use fred::types::{Expiration, SetOptions};
+use std::io;
+use std::time::Duration;
+
+/// How long a lock is held at most, in case its holder dies before releasing it
+const LOCK_TIMEOUT_IN_MILLISECONDS: i64 = 30_000;
+
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(20);
+
+/// `KEYS`: hash key of the id, refs key of the hash. `ARGV`: id, hash
+const LINK_SCRIPT: &str = r#"
+redis.call('SET', KEYS[1], ARGV[2])
+redis.call('SADD', KEYS[2], ARGV[1])
+"#;
+
+/// `KEYS`: hash key of the id, refs key of the hash. `ARGV`: id, hash
+const UNLINK_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) ~= ARGV[2] then
+    return -1
+end
+redis.call('DEL', KEYS[1])
+redis.call('SREM', KEYS[2], ARGV[1])
+return redis.call('SCARD', KEYS[2])
+"#;
+
+/// `KEYS`: hash keys of both ids, refs key of the hash. `ARGV`: both ids, hash.
+/// The reference keeps expiring when it did before.
+const RELINK_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) ~= ARGV[3] then
+    return 0
+end
+local ttl = redis.call('PTTL', KEYS[1])
+redis.call('DEL', KEYS[1])
+redis.call('SREM', KEYS[3], ARGV[1])
+if ttl > 0 then
+    redis.call('SET', KEYS[2], ARGV[3], 'PX', ttl)
+else
+    redis.call('SET', KEYS[2], ARGV[3])
+end
+redis.call('SADD', KEYS[3], ARGV[2])
+return 1
+"#;
+
+/// `KEYS`: refs key of the hash. `ARGV`: hash.
+/// The hash keys of the ids are only known once the refs are read, they're built like
+/// [`RedisBlobRefs::hash_key`] does.
+const PRUNE_SCRIPT: &str = r#"
+local live = {}
+for _, id in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    if redis.call('GET', id .. ':blob') == ARGV[1] then
+        table.insert(live, id)
+    else
+        redis.call('SREM', KEYS[1], id)
+    end
+end
+return live
+"#;
+
+/// `KEYS`: lock key. `ARGV`: token of the holder
+const UNLOCK_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// Which blob of a [`DedupStore`](crate::storage::dedup::DedupStore) every paste refers to.
+///
+/// Every change of a reference is atomic. Storing or removing the blob itself happens outside
+/// of it, so that's done while holding the [`BlobRefs::lock`] of the blob.
+#[async_trait]
+pub trait BlobRefs: Send + Sync {
+    /// Hash of the blob `id` refers to
+    async fn hash(&self, id: &str) -> io::Result<Option<String>>;
+
+    /// Points `id` to the blob of `hash`.
+    async fn link(&self, id: &str, hash: &str) -> io::Result<()>;
+
+    /// Removes the reference of `id` to the blob of `hash` and returns how many ids still
+    /// refer to it, `None` if `id` didn't refer to it.
+    async fn unlink(&self, id: &str, hash: &str) -> io::Result<Option<u64>>;
+
+    /// Moves the reference of `from` to the blob of `hash` over to `to`.
+    /// Returns `false` if `from` didn't refer to it.
+    async fn relink(&self, from: &str, to: &str, hash: &str) -> io::Result<bool>;
+
+    /// Makes the reference of `id` expire at the given unix timestamp, or never for `None`.
+    /// An expired reference stays in the refs of its blob until [`BlobRefs::prune`].
+    async fn expire(&self, id: &str, expires_at: Option<u64>) -> io::Result<()>;
+
+    /// Drops the expired references from the refs of the blob of `hash` and returns the ids
+    /// still referring to it.
+    async fn prune(&self, hash: &str) -> io::Result<Vec<String>>;
+
+    /// Waits until nobody else holds the lock of the blob of `hash` and takes it.
+    /// Returns the token to release it with.
+    async fn lock(&self, hash: &str) -> io::Result<String>;
+
+    async fn unlock(&self, hash: &str, token: &str) -> io::Result<()>;
+}
+
+/// Keeps the references in Redis, `<id>:blob` holds the hash an id refers to and expires with
+/// the paste, `blob:<hash>:refs` the set of ids referring to a blob.
+pub struct RedisBlobRefs {
+    pool: RedisPool,
+}
+
+impl RedisBlobRefs {
+    pub fn new(pool: RedisPool) -> Self {
+        Self { pool }
+    }
+
+    fn hash_key(id: &str) -> String {
+        format!("{id}:blob")
+    }
+
+    fn refs_key(hash: &str) -> String {
+        format!("blob:{hash}:refs")
+    }
+
+    fn lock_key(hash: &str) -> String {
+        format!("blob:{hash}:lock")
+    }
+}
+
+fn redis_error(err: RedisError) -> io::Error {
+    io::Error::other(err)
+}
+
+#[async_trait]
+impl BlobRefs for RedisBlobRefs {
+    async fn hash(&self, id: &str) -> io::Result<Option<String>> {
+        self.pool.get(Self::hash_key(id)).await.map_err(redis_error)
+    }
+
+    async fn link(&self, id: &str, hash: &str) -> io::Result<()> {
+        self.pool
+            .eval(
+                LINK_SCRIPT,
+                vec![Self::hash_key(id), Self::refs_key(hash)],
+                vec![id.to_string(), hash.to_string()],
+            )
+            .await
+            .map_err(redis_error)
+    }
+
+    async fn unlink(&self, id: &str, hash: &str) -> io::Result<Option<u64>> {
+        let left: i64 = self
+            .pool
+            .eval(
+                UNLINK_SCRIPT,
+                vec![Self::hash_key(id), Self::refs_key(hash)],
+                vec![id.to_string(), hash.to_string()],
+            )
+            .await
+            .map_err(redis_error)?;
+
+        Ok(u64::try_from(left).ok())
+    }
+
+    async fn relink(&self, from: &str, to: &str, hash: &str) -> io::Result<bool> {
+        let moved: i64 = self
+            .pool
+            .eval(
+                RELINK_SCRIPT,
+                vec![
+                    Self::hash_key(from),
+                    Self::hash_key(to),
+                    Self::refs_key(hash),
+                ],
+                vec![from.to_string(), to.to_string(), hash.to_string()],
+            )
+            .await
+            .map_err(redis_error)?;
+
+        Ok(moved == 1)
+    }
+
+    async fn expire(&self, id: &str, expires_at: Option<u64>) -> io::Result<()> {
+        let key = Self::hash_key(id);
+
+        let _: () = match expires_at {
+            Some(expires_at) => self.pool.expire_at(key, expires_at as i64).await,
+            None => self.pool.persist(key).await,
+        }
+        .map_err(redis_error)?;
+
+        Ok(())
+    }
+
+    async fn prune(&self, hash: &str) -> io::Result<Vec<String>> {
+        self.pool
+            .eval(
+                PRUNE_SCRIPT,
+                vec![Self::refs_key(hash)],
+                vec![hash.to_string()],
+            )
+            .await
+            .map_err(redis_error)
+    }
+
+    async fn lock(&self, hash: &str) -> io::Result<String> {
+        let token = gen_id(16);
+
+        loop {
+            let locked: Option<String> = self
+                .pool
+                .set(
+                    Self::lock_key(hash),
+                    token.as_str(),
+                    Some(Expiration::PX(LOCK_TIMEOUT_IN_MILLISECONDS)),
+                    Some(SetOptions::NX),
+                    false,
+                )
+                .await
+                .map_err(redis_error)?;
+
+            if locked.is_some() {
+                return Ok(token);
+            }
+
+            tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+        }
+    }
+
+    async fn unlock(&self, hash: &str, token: &str) -> io::Result<()> {
+        let _: i64 = self
+            .pool
+            .eval(UNLOCK_SCRIPT, vec![Self::lock_key(hash)], vec![token])
+            .await
+            .map_err(redis_error)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use crate::configuration::S3Storage;
+use crate::storage::{PasteReader, PasteStore};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use object_store::aws::AmazonS3Builder;
+use object_store::buffered::BufWriter;
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload};
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio_util::io::StreamReader;
+
+/// Stores pastes as objects in an S3 compatible bucket (AWS, MinIO, Garage, ...).
+pub struct S3Store {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+}
+
+impl S3Store {
+    pub fn new(configuration: &S3Storage) -> object_store::Result<Self> {
+        let store = AmazonS3Builder::new()
+            .with_bucket_name(&configuration.bucket)
+            .with_region(&configuration.region)
+            .with_endpoint(&configuration.endpoint)
+            .with_access_key_id(&configuration.access_key_id)
+            .with_secret_access_key(&configuration.secret_access_key)
+            .with_allow_http(configuration.endpoint.starts_with("http://"))
+            .with_virtual_hosted_style_request(!configuration.path_style)
+            .build()?;
+
+        Ok(Self {
+            store: Arc::new(store),
+            prefix: Path::from(configuration.prefix.as_str()),
+        })
+    }
+
+    /// Moves the store to a nested prefix, which isn't listed by a store without one
+    pub fn namespaced(mut self, namespace: &str) -> Self {
+        self.prefix = self.prefix.child(namespace);
+        self
+    }
+
+    fn path(&self, id: &str) -> Path {
+        self.prefix.child(id)
+    }
+}
+
+#[async_trait]
+impl PasteStore for S3Store {
+    async fn put(&self, id: &str, content: &[u8]) -> io::Result<()> {
+        self.store
+            .put(&self.path(id), PutPayload::from(content.to_vec()))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn put_stream(
+        &self,
+        id: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> io::Result<u64> {
+        // switches to a multipart upload once the paste outgrows the buffer
+        let mut writer = BufWriter::new(self.store.clone(), self.path(id));
+
+        let written = match tokio::io::copy(reader, &mut writer).await {
+            Ok(written) => written,
+            Err(err) => {
+                writer.abort().await?;
+                return Err(err);
+            }
+        };
+        writer.shutdown().await?;
+
+        Ok(written)
+    }
+
+    async fn get(&self, id: &str) -> io::Result<Vec<u8>> {
+        let content = self.store.get(&self.path(id)).await?.bytes().await?;
+
+        Ok(content.to_vec())
+    }
+
+    async fn reader(&self, id: &str) -> io::Result<PasteReader> {
+        let stream = self
+            .store
+            .get(&self.path(id))
+            .await?
+            .into_stream()
+            .map_err(io::Error::from);
+
+        Ok(Box::new(StreamReader::new(stream)))
+    }
+
+    async fn delete(&self, id: &str) -> io::Result<()> {
+        self.store.delete(&self.path(id)).await?;
+
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        self.store.rename(&self.path(from), &self.path(to)).await?;
+
+        Ok(())
+    }
+
+    async fn exists(&self, id: &str) -> io::Result<bool> {
+        match self.store.head(&self.path(id)).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn size(&self, id: &str) -> io::Result<u64> {
+        let object = self.store.head(&self.path(id)).await?;
+
+        Ok(object.size as u64)
+    }
+
+    async fn list(&self) -> io::Result<Vec<String>> {
+        let objects: Vec<_> = self.store.list(Some(&self.prefix)).try_collect().await?;
+
+        // listing is recursive, objects of namespaces are nested deeper
+        let ids = objects
+            .into_iter()
+            .filter_map(|object| {
+                let id = object.location.filename()?;
+                (self.path(id) == object.location).then(|| id.to_string())
+            })
+            .collect();
+
+        Ok(ids)
+    }
+}
@@ -0,0 +1,215 @@
+use crate::configuration::Settings;
+use crate::paste::cache;
+use crate::paste::metadata::{unix_timestamp, PasteMetadata};
+use crate::paste::Paste;
+use crate::routes::post::expiration_in_seconds;
+use crate::storage::dedup::DedupStore;
+use crate::storage::PasteStore;
+use crate::worker::WorkerPool;
+use fred::clients::RedisPool;
+use fred::interfaces::KeysInterface;
+use std::collections::HashSet;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, Instant};
+use tracing::{error, info, warn};
+
+/// Removes stored pastes that expired, and rebuilds or removes missing metadata.
+pub struct Sweeper {
+    pool: RedisPool,
+    store: Arc<DedupStore>,
+    configuration: Settings,
+    workers: WorkerPool,
+    /// Pastes without metadata seen by the previous sweep
+    orphans: HashSet<String>,
+    /// Blobs nobody referred to in the previous sweep
+    unreferenced_blobs: HashSet<String>,
+}
+
+#[derive(Default)]
+pub struct SweepStats {
+    pub removed: usize,
+    pub freed_bytes: u64,
+    /// Pastes without metadata that got it rebuilt
+    pub restored: usize,
+    /// Pastes without metadata that were removed, included in `removed`
+    pub removed_orphans: usize,
+    /// Deduplicated content nobody referred to anymore, not included in `removed`
+    pub removed_blobs: usize,
+}
+
+impl Sweeper {
+    pub fn new(
+        pool: RedisPool,
+        store: Arc<DedupStore>,
+        configuration: Settings,
+        workers: WorkerPool,
+    ) -> Self {
+        Self {
+            pool,
+            store,
+            configuration,
+            workers,
+            orphans: HashSet::new(),
+            unreferenced_blobs: HashSet::new(),
+        }
+    }
+
+    /// An upload that's still being streamed (possibly by another instance sharing the storage)
+    /// has no metadata yet, so pastes without metadata are only handled once two sweeps in a
+    /// row found them.
+    pub async fn sweep(&mut self) -> io::Result<SweepStats> {
+        self.run(false).await
+    }
+
+    /// Like [`Sweeper::sweep`], but pastes without metadata get it rebuilt right away, they
+    /// can't be viewed until then. Removing them still waits for the next sweep.
+    pub async fn sweep_at_startup(&mut self) -> io::Result<SweepStats> {
+        self.run(true).await
+    }
+
+    async fn run(&mut self, at_startup: bool) -> io::Result<SweepStats> {
+        let remove_orphans = self.configuration.storage.remove_orphans;
+        let now = unix_timestamp();
+        let mut stats = SweepStats::default();
+        let mut orphans = HashSet::new();
+
+        for id in self.store.list().await? {
+            let metadata = PasteMetadata::load(&self.pool, &id)
+                .await
+                .map_err(io::Error::other)?;
+
+            // the metadata of expired pastes is gone, their expiration isn't
+            let expires_at = match &metadata {
+                Some(metadata) => metadata.expires_at,
+                None => PasteMetadata::expiration(&self.pool, &id)
+                    .await
+                    .map_err(io::Error::other)?,
+            };
+
+            match metadata {
+                Some(metadata) if metadata.is_legacy() => {
+                    if let Err(err) = self.rebuild_metadata(&id, None).await {
+                        warn!("Couldn't upgrade legacy metadata of '{id}': {err}");
+                    }
+                }
+                _ if expires_at.is_some_and(|at| at <= now) => {
+                    let _: () = self.pool.del(&id).await.map_err(io::Error::other)?;
+                    self.remove(&id, &mut stats).await?;
+                }
+                Some(_) => {}
+                None if remove_orphans && self.orphans.contains(&id) => {
+                    self.remove(&id, &mut stats).await?;
+                    stats.removed_orphans += 1;
+                }
+                None if !remove_orphans && (at_startup || self.orphans.contains(&id)) => {
+                    match self.rebuild_metadata(&id, expires_at).await {
+                        Ok(()) => stats.restored += 1,
+                        Err(err) => warn!("Couldn't rebuild metadata of '{id}': {err}"),
+                    }
+                }
+                None => {
+                    orphans.insert(id);
+                }
+            }
+        }
+
+        self.orphans = orphans;
+
+        // every paste that expired by now was removed above
+        PasteMetadata::forget_expired(&self.pool, now)
+            .await
+            .map_err(io::Error::other)?;
+
+        // blobs are unreferenced while they're uploaded, so they get the same grace period
+        let mut unreferenced_blobs = HashSet::new();
+        for blob in self.store.unreferenced_blobs().await? {
+            if !self.unreferenced_blobs.contains(&blob) {
+                unreferenced_blobs.insert(blob);
+            } else if let Some(size) = self.store.remove_blob(&blob).await? {
+                stats.removed_blobs += 1;
+                stats.freed_bytes += size;
+            }
+        }
+        self.unreferenced_blobs = unreferenced_blobs;
+
+        if stats.removed_orphans > 0 {
+            warn!(
+                "Sweeper removed {} pastes without metadata, if Redis lost its data \
+                 disable storage.remove_orphans to keep them",
+                stats.removed_orphans
+            );
+        }
+        if stats.restored > 0 {
+            warn!(
+                "Sweeper rebuilt the metadata of {} pastes without it",
+                stats.restored
+            );
+        }
+
+        info!(
+            "Sweeper removed {} pastes and {} unreferenced blobs and freed {} bytes",
+            stats.removed, stats.removed_blobs, stats.freed_bytes
+        );
+
+        Ok(stats)
+    }
+
+    async fn remove(&self, id: &str, stats: &mut SweepStats) -> io::Result<()> {
+        let size = self.store.size(id).await?;
+        self.store.delete(id).await?;
+        cache::delete(&self.pool, id)
+            .await
+            .map_err(io::Error::other)?;
+
+        stats.removed += 1;
+        stats.freed_bytes += size;
+
+        Ok(())
+    }
+
+    /// Generates the metadata of a legacy paste or one whose metadata is missing. The paste
+    /// keeps expiring at `expires_at` if its expiration is known, otherwise it expires like a
+    /// new paste.
+    async fn rebuild_metadata(&self, id: &str, expires_at: Option<u64>) -> io::Result<()> {
+        let content = self.store.get(id).await?;
+        let paste = Paste::new(id.to_string(), self.store.clone());
+
+        let expires_in = match expires_at {
+            Some(expires_at) => Some(expires_at.saturating_sub(unix_timestamp())),
+            None => expiration_in_seconds(&self.configuration),
+        };
+
+        let metadata = PasteMetadata::generate(
+            &paste,
+            content,
+            expires_in,
+            &self.configuration,
+            &self.workers,
+        )
+        .await
+        .map_err(|err| io::Error::other(err.to_string()))?;
+
+        metadata
+            .save(&self.pool, id)
+            .await
+            .map_err(io::Error::other)?;
+        self.store.expire(id, metadata.expires_at).await
+    }
+
+    pub fn spawn(mut self, period: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = interval_at(Instant::now() + period, period);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(err) = self.sweep().await {
+                    error!("Sweeping pastes failed: {err}");
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,96 @@
+use crate::configuration::AnalyzerWorkers;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// Seconds clients are told to wait when the pool is saturated
+pub const RETRY_AFTER_IN_SECONDS: u64 = 5;
+
+/// Runs synchronous CPU heavy work (scripts, analysis, rendering) on a bounded number
+/// of blocking threads, so it doesn't stall the async runtime.
+#[derive(Clone)]
+pub struct WorkerPool {
+    threads: Arc<Semaphore>,
+    /// Tasks that are waiting or running
+    pending: Arc<AtomicUsize>,
+    capacity: usize,
+    timeout: Duration,
+}
+
+#[derive(Debug)]
+pub enum WorkerError {
+    /// All threads are busy and the queue is full
+    Busy,
+    TimedOut,
+    Panicked,
+}
+
+impl fmt::Display for WorkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkerError::Busy => write!(f, "all workers are busy"),
+            WorkerError::TimedOut => write!(f, "task timed out"),
+            WorkerError::Panicked => write!(f, "task panicked"),
+        }
+    }
+}
+
+/// Frees the slot of a task once it's finished or dropped before it started.
+struct Pending(Arc<AtomicUsize>);
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl WorkerPool {
+    pub fn new(configuration: &AnalyzerWorkers) -> Self {
+        let threads = configuration.threads.max(1);
+
+        Self {
+            threads: Arc::new(Semaphore::new(threads)),
+            pending: Arc::new(AtomicUsize::new(0)),
+            capacity: threads + configuration.queue_size,
+            timeout: Duration::from_secs(configuration.timeout_in_seconds),
+        }
+    }
+
+    /// Runs `task` on a worker thread once one is free.
+    ///
+    /// The caller stops waiting after the timeout, but the thread can't be interrupted, so it
+    /// stays taken until the task is finished.
+    pub async fn run<F, T>(&self, task: F) -> Result<T, WorkerError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        if self.pending.fetch_add(1, Ordering::AcqRel) >= self.capacity {
+            self.pending.fetch_sub(1, Ordering::AcqRel);
+            return Err(WorkerError::Busy);
+        }
+        let pending = Pending(self.pending.clone());
+
+        let permit = self
+            .threads
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Worker semaphore is never closed");
+
+        let handle = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let _pending = pending;
+
+            task()
+        });
+
+        match tokio::time::timeout(self.timeout, handle).await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(_)) => Err(WorkerError::Panicked),
+            Err(_) => Err(WorkerError::TimedOut),
+        }
+    }
+}